@@ -0,0 +1,240 @@
+// Minimal gzip (RFC 1952) writer: the data is compressed into a single deflate
+// block using the fixed Huffman codes, which is good enough for the highly
+// repetitive GPX text. The reader is there to check what was written.
+use crate::inflate::inflate;
+use std::io::{Error, ErrorKind, Result};
+
+const WINDOW: usize = 32768;
+const MIN_MATCH: usize = 3;
+const MAX_MATCH: usize = 258;
+const MAX_CHAIN: usize = 64; // Match candidates examined at each position.
+const HASH_BITS: u32 = 15;
+
+const LENGTH_BASE: [u16; 29] = [
+    3, 4, 5, 6, 7, 8, 9, 10, 11, 13, 15, 17, 19, 23, 27, 31, 35, 43, 51, 59, 67, 83, 99, 115, 131,
+    163, 195, 227, 258,
+];
+const LENGTH_EXTRA: [u8; 29] = [
+    0, 0, 0, 0, 0, 0, 0, 0, 1, 1, 1, 1, 2, 2, 2, 2, 3, 3, 3, 3, 4, 4, 4, 4, 5, 5, 5, 5, 0,
+];
+const DIST_BASE: [u16; 30] = [
+    1, 2, 3, 4, 5, 7, 9, 13, 17, 25, 33, 49, 65, 97, 129, 193, 257, 385, 513, 769, 1025, 1537,
+    2049, 3073, 4097, 6145, 8193, 12289, 16385, 24577,
+];
+const DIST_EXTRA: [u8; 30] = [
+    0, 0, 0, 0, 1, 1, 2, 2, 3, 3, 4, 4, 5, 5, 6, 6, 7, 7, 8, 8, 9, 9, 10, 10, 11, 11, 12, 12, 13,
+    13,
+];
+
+// Deflate output, bits are packed starting with the least significant one.
+struct Bits {
+    out: Vec<u8>,
+    buf: u32,
+    count: u32,
+}
+
+impl Bits {
+    fn put(&mut self, value: u32, count: u32) {
+        self.buf |= value << self.count;
+        self.count += count;
+        while self.count >= 8 {
+            self.out.push(self.buf as u8);
+            self.buf >>= 8;
+            self.count -= 8;
+        }
+    }
+
+    // Huffman codes are stored starting with the most significant bit.
+    fn code(&mut self, code: u32, len: u32) {
+        self.put(code.reverse_bits() >> (32 - len), len);
+    }
+
+    fn flush(&mut self) {
+        if self.count > 0 {
+            self.out.push(self.buf as u8);
+        }
+        self.buf = 0;
+        self.count = 0;
+    }
+}
+
+// Literal/length symbol with the fixed code.
+fn symbol(bits: &mut Bits, symbol: u32) {
+    match symbol {
+        0..=143 => bits.code(0x30 + symbol, 8),
+        144..=255 => bits.code(0x190 + symbol - 144, 9),
+        256..=279 => bits.code(symbol - 256, 7),
+        _ => bits.code(0xc0 + symbol - 280, 8),
+    }
+}
+
+fn copy(bits: &mut Bits, len: usize, dist: usize) {
+    let l = LENGTH_BASE
+        .iter()
+        .rposition(|&b| b as usize <= len)
+        .unwrap();
+    symbol(bits, 257 + l as u32);
+    bits.put(
+        (len - LENGTH_BASE[l] as usize) as u32,
+        LENGTH_EXTRA[l] as u32,
+    );
+
+    let d = DIST_BASE.iter().rposition(|&b| b as usize <= dist).unwrap();
+    bits.code(d as u32, 5);
+    bits.put((dist - DIST_BASE[d] as usize) as u32, DIST_EXTRA[d] as u32);
+}
+
+fn hash(data: &[u8]) -> usize {
+    let v = u32::from(data[0]) << 16 | u32::from(data[1]) << 8 | u32::from(data[2]);
+    (v.wrapping_mul(0x9e37_79b1) >> (32 - HASH_BITS)) as usize
+}
+
+// Longest earlier match of the data at `pos`, following the hash chain.
+fn longest_match(data: &[u8], pos: usize, head: &[usize], prev: &[usize]) -> (usize, usize) {
+    let limit = (data.len() - pos).min(MAX_MATCH);
+    let (mut best_len, mut best_dist) = (0, 0);
+    let mut candidate = head[hash(&data[pos..])];
+
+    for _ in 0..MAX_CHAIN {
+        if candidate == usize::MAX || pos - candidate > WINDOW {
+            break;
+        }
+        let len = data[candidate..]
+            .iter()
+            .zip(&data[pos..pos + limit])
+            .take_while(|(a, b)| a == b)
+            .count();
+        if len > best_len {
+            best_len = len;
+            best_dist = pos - candidate;
+            if len == limit {
+                break;
+            }
+        }
+        candidate = prev[candidate % WINDOW];
+    }
+    (best_len, best_dist)
+}
+
+fn deflate(data: &[u8]) -> Vec<u8> {
+    let mut bits = Bits {
+        out: Vec::new(),
+        buf: 0,
+        count: 0,
+    };
+    let mut head = vec![usize::MAX; 1 << HASH_BITS];
+    let mut prev = vec![usize::MAX; WINDOW];
+    let mut pos = 0;
+
+    bits.put(1, 1); // Final block.
+    bits.put(1, 2); // Fixed Huffman codes.
+    while pos < data.len() {
+        let (len, dist) = if data.len() - pos >= MIN_MATCH {
+            longest_match(data, pos, &head, &prev)
+        } else {
+            (0, 0)
+        };
+        let step = if len >= MIN_MATCH {
+            copy(&mut bits, len, dist);
+            len
+        } else {
+            symbol(&mut bits, u32::from(data[pos]));
+            1
+        };
+        for p in pos..(pos + step).min(data.len().saturating_sub(MIN_MATCH - 1)) {
+            let h = hash(&data[p..]);
+            prev[p % WINDOW] = head[h];
+            head[h] = p;
+        }
+        pos += step;
+    }
+    symbol(&mut bits, 256); // End of block.
+    bits.flush();
+    bits.out
+}
+
+pub fn crc32(data: &[u8]) -> u32 {
+    let mut crc = !0u32;
+
+    for &byte in data {
+        crc ^= u32::from(byte);
+        for _ in 0..8 {
+            crc = if crc & 1 != 0 {
+                (crc >> 1) ^ 0xedb8_8320
+            } else {
+                crc >> 1
+            };
+        }
+    }
+    !crc
+}
+
+const FHCRC: u8 = 0x02; // Header CRC present.
+const FEXTRA: u8 = 0x04; // Extra field present.
+const FNAME: u8 = 0x08; // File name present.
+const FCOMMENT: u8 = 0x10; // Comment present.
+
+fn bad_gzip(why: &str) -> Error {
+    Error::new(ErrorKind::InvalidData, format!("bad gzip data: {}", why))
+}
+
+// Wrap the data into a gzip member, with no file name or time stamp.
+pub fn compress(data: &[u8]) -> Vec<u8> {
+    let mut out = vec![0x1f, 0x8b, 8, 0, 0, 0, 0, 0, 0, 0xff];
+
+    out.extend(deflate(data));
+    out.extend_from_slice(&crc32(data).to_le_bytes());
+    out.extend_from_slice(&(data.len() as u32).to_le_bytes());
+    out
+}
+
+// Unwrap the data of a single gzip member, checking its CRC and size.
+pub fn decompress(data: &[u8]) -> Result<Vec<u8>> {
+    if data.len() < 18 || data[..3] != [0x1f, 0x8b, 8] {
+        return Err(bad_gzip("bad header"));
+    }
+    let flags = data[3];
+    let mut pos = 10;
+
+    if flags & FEXTRA != 0 {
+        let len = data
+            .get(pos..pos + 2)
+            .ok_or_else(|| bad_gzip("truncated"))?;
+        pos += 2 + u16::from_le_bytes([len[0], len[1]]) as usize;
+    }
+    for flag in [FNAME, FCOMMENT] {
+        if flags & flag != 0 {
+            let end = data
+                .get(pos..)
+                .and_then(|rest| rest.iter().position(|&b| b == 0))
+                .ok_or_else(|| bad_gzip("truncated"))?;
+            pos += end + 1;
+        }
+    }
+    if flags & FHCRC != 0 {
+        pos += 2;
+    }
+
+    let trailer = data.len() - 8;
+    let crc = u32::from_le_bytes([
+        data[trailer],
+        data[trailer + 1],
+        data[trailer + 2],
+        data[trailer + 3],
+    ]);
+    let size = u32::from_le_bytes([
+        data[trailer + 4],
+        data[trailer + 5],
+        data[trailer + 6],
+        data[trailer + 7],
+    ]);
+    let out = inflate(
+        data.get(pos..trailer)
+            .ok_or_else(|| bad_gzip("truncated"))?,
+        size as usize,
+    )?;
+    if out.len() != size as usize || crc32(&out) != crc {
+        return Err(bad_gzip("CRC or size mismatch"));
+    }
+    Ok(out)
+}
@@ -0,0 +1,252 @@
+// Deflate (RFC 1951) decoder, modeled after zlib's puff.c. Used to read the
+// deflated ZIP entries and to check the gzip output.
+use std::io::{Error, ErrorKind, Result};
+
+fn bad_data(why: &str) -> Error {
+    Error::new(ErrorKind::InvalidData, format!("compressed data: {}", why))
+}
+
+struct Bits<'a> {
+    data: &'a [u8],
+    pos: usize,
+    buf: u32,
+    count: u32,
+}
+
+impl<'a> Bits<'a> {
+    fn bits(&mut self, need: u32) -> Result<u32> {
+        while self.count < need {
+            let byte = *self
+                .data
+                .get(self.pos)
+                .ok_or_else(|| bad_data("truncated"))?;
+            self.buf |= (byte as u32) << self.count;
+            self.pos += 1;
+            self.count += 8;
+        }
+
+        let value = self.buf & ((1u32 << need) - 1);
+        self.buf >>= need;
+        self.count -= need;
+        Ok(value)
+    }
+}
+
+const MAX_BITS: usize = 15;
+
+// Canonical Huffman code: number of codes of each length and the symbols
+// ordered by code.
+struct Huffman {
+    counts: [u16; MAX_BITS + 1],
+    symbols: Vec<u16>,
+}
+
+impl Huffman {
+    fn new(lengths: &[u8]) -> Self {
+        let mut counts = [0u16; MAX_BITS + 1];
+        let mut offsets = [0u16; MAX_BITS + 2];
+
+        for &len in lengths {
+            counts[len as usize] += 1;
+        }
+        counts[0] = 0;
+        for len in 1..=MAX_BITS {
+            offsets[len + 1] = offsets[len] + counts[len];
+        }
+
+        let mut symbols = vec![0u16; offsets[MAX_BITS + 1] as usize];
+        for (symbol, &len) in lengths.iter().enumerate() {
+            if len != 0 {
+                symbols[offsets[len as usize] as usize] = symbol as u16;
+                offsets[len as usize] += 1;
+            }
+        }
+        Self { counts, symbols }
+    }
+
+    fn decode(&self, bits: &mut Bits) -> Result<u16> {
+        let (mut code, mut first, mut index) = (0i32, 0i32, 0i32);
+
+        for len in 1..=MAX_BITS {
+            code |= bits.bits(1)? as i32;
+            let count = self.counts[len] as i32;
+            if code - first < count {
+                return Ok(self.symbols[(index + code - first) as usize]);
+            }
+            index += count;
+            first = (first + count) << 1;
+            code <<= 1;
+        }
+        Err(bad_data("bad code"))
+    }
+}
+
+const LENGTH_BASE: [u16; 29] = [
+    3, 4, 5, 6, 7, 8, 9, 10, 11, 13, 15, 17, 19, 23, 27, 31, 35, 43, 51, 59, 67, 83, 99, 115, 131,
+    163, 195, 227, 258,
+];
+const LENGTH_EXTRA: [u8; 29] = [
+    0, 0, 0, 0, 0, 0, 0, 0, 1, 1, 1, 1, 2, 2, 2, 2, 3, 3, 3, 3, 4, 4, 4, 4, 5, 5, 5, 5, 0,
+];
+const DIST_BASE: [u16; 30] = [
+    1, 2, 3, 4, 5, 7, 9, 13, 17, 25, 33, 49, 65, 97, 129, 193, 257, 385, 513, 769, 1025, 1537,
+    2049, 3073, 4097, 6145, 8193, 12289, 16385, 24577,
+];
+const DIST_EXTRA: [u8; 30] = [
+    0, 0, 0, 0, 1, 1, 2, 2, 3, 3, 4, 4, 5, 5, 6, 6, 7, 7, 8, 8, 9, 9, 10, 10, 11, 11, 12, 12, 13,
+    13,
+];
+// Order in which code length code lengths are stored in dynamic blocks.
+const CODE_LENGTH_ORDER: [usize; 19] = [
+    16, 17, 18, 0, 8, 7, 9, 6, 10, 5, 11, 4, 12, 3, 13, 2, 14, 1, 15,
+];
+
+// Decompress the data, which must not come out longer than `size` bytes.
+pub fn inflate(data: &[u8], size: usize) -> Result<Vec<u8>> {
+    let mut bits = Bits {
+        data,
+        pos: 0,
+        buf: 0,
+        count: 0,
+    };
+    // The size comes with the data, do not trust it with the allocation.
+    let mut out: Vec<u8> = Vec::with_capacity(size.min(data.len() * 4));
+
+    loop {
+        let last = bits.bits(1)? == 1;
+
+        match bits.bits(2)? {
+            0 => stored_block(&mut bits, &mut out, size)?,
+            1 => {
+                let mut lengths = [0u8; 288 + 30];
+                lengths[..144].fill(8);
+                lengths[144..256].fill(9);
+                lengths[256..280].fill(7);
+                lengths[280..288].fill(8);
+                lengths[288..].fill(5);
+                let lit = Huffman::new(&lengths[..288]);
+                let dist = Huffman::new(&lengths[288..]);
+                codes(&mut bits, &mut out, size, &lit, &dist)?;
+            }
+            2 => {
+                let (lit, dist) = dynamic_codes(&mut bits)?;
+                codes(&mut bits, &mut out, size, &lit, &dist)?;
+            }
+            _ => return Err(bad_data("bad block type")),
+        }
+        if last {
+            return Ok(out);
+        }
+    }
+}
+
+// Blocks must not produce more than `limit` bytes in total.
+fn stored_block(bits: &mut Bits, out: &mut Vec<u8>, limit: usize) -> Result<()> {
+    // Stored blocks start at a byte boundary.
+    bits.buf = 0;
+    bits.count = 0;
+
+    let header = bits
+        .data
+        .get(bits.pos..bits.pos + 4)
+        .ok_or_else(|| bad_data("truncated"))?;
+    let len = u16::from_le_bytes([header[0], header[1]]) as usize;
+    let nlen = u16::from_le_bytes([header[2], header[3]]) as usize;
+    if len != !nlen & 0xffff || out.len() + len > limit {
+        return Err(bad_data("bad stored block"));
+    }
+
+    let start = bits.pos + 4;
+    let block = bits
+        .data
+        .get(start..start + len)
+        .ok_or_else(|| bad_data("truncated"))?;
+    out.extend_from_slice(block);
+    bits.pos = start + len;
+    Ok(())
+}
+
+fn dynamic_codes(bits: &mut Bits) -> Result<(Huffman, Huffman)> {
+    let nlen = bits.bits(5)? as usize + 257;
+    let ndist = bits.bits(5)? as usize + 1;
+    let ncode = bits.bits(4)? as usize + 4;
+    let mut lengths = [0u8; 288 + 32];
+
+    if nlen > 286 || ndist > 30 {
+        return Err(bad_data("bad code lengths"));
+    }
+    for &index in CODE_LENGTH_ORDER.iter().take(ncode) {
+        lengths[index] = bits.bits(3)? as u8;
+    }
+    let code_lengths = Huffman::new(&lengths[..19]);
+
+    let mut index = 0;
+    while index < nlen + ndist {
+        let symbol = code_lengths.decode(bits)?;
+        let (value, repeat) = match symbol {
+            0..=15 => (symbol as u8, 1),
+            16 => {
+                if index == 0 {
+                    return Err(bad_data("bad code lengths"));
+                }
+                (lengths[index - 1], 3 + bits.bits(2)? as usize)
+            }
+            17 => (0, 3 + bits.bits(3)? as usize),
+            _ => (0, 11 + bits.bits(7)? as usize),
+        };
+        if index + repeat > nlen + ndist {
+            return Err(bad_data("bad code lengths"));
+        }
+        lengths[index..index + repeat].fill(value);
+        index += repeat;
+    }
+
+    Ok((
+        Huffman::new(&lengths[..nlen]),
+        Huffman::new(&lengths[nlen..nlen + ndist]),
+    ))
+}
+
+fn codes(
+    bits: &mut Bits,
+    out: &mut Vec<u8>,
+    limit: usize,
+    lit: &Huffman,
+    dist: &Huffman,
+) -> Result<()> {
+    loop {
+        let symbol = lit.decode(bits)? as usize;
+
+        if symbol != 256 && out.len() >= limit {
+            return Err(bad_data("longer than expected"));
+        }
+        if symbol < 256 {
+            out.push(symbol as u8);
+            continue;
+        }
+        if symbol == 256 {
+            return Ok(());
+        }
+
+        let symbol = symbol - 257;
+        if symbol >= LENGTH_BASE.len() {
+            return Err(bad_data("bad length"));
+        }
+        let len = LENGTH_BASE[symbol] as usize + bits.bits(LENGTH_EXTRA[symbol] as u32)? as usize;
+
+        let symbol = dist.decode(bits)? as usize;
+        if symbol >= DIST_BASE.len() {
+            return Err(bad_data("bad distance"));
+        }
+        let distance = DIST_BASE[symbol] as usize + bits.bits(DIST_EXTRA[symbol] as u32)? as usize;
+        if distance > out.len() || out.len() + len > limit {
+            return Err(bad_data("bad copy"));
+        }
+
+        // The copy can overlap the bytes it produces.
+        let start = out.len() - distance;
+        for i in 0..len {
+            out.push(out[start + i]);
+        }
+    }
+}
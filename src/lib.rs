@@ -0,0 +1,6764 @@
+// Retrieve the geotags of photos and videos and generate a map of them. The
+// command line tool is a thin wrapper calling run(); parse_file() and the
+// write_*() map writers are there for programs wanting the geotags without
+// running it.
+extern crate getopts;
+use getopts::{Matches, Options};
+use std::borrow::Cow;
+use std::cmp::Ordering;
+use std::collections::{HashMap, HashSet};
+use std::convert::TryFrom;
+use std::ffi::OsString;
+use std::fs::{self, File, OpenOptions};
+use std::io::{self, Cursor, Error, ErrorKind, Read, Result, Seek, SeekFrom, Write};
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicUsize, Ordering as AtomicOrdering};
+use std::sync::{mpsc, Mutex};
+use std::thread;
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+use std::{char, env, fmt, str};
+
+#[cfg(feature = "gzip")]
+mod gzip;
+#[cfg(any(feature = "gzip", feature = "zip"))]
+mod inflate;
+mod quicktime;
+#[cfg(feature = "zip")]
+mod zip;
+
+const SOI: u16 = 0xffd8; // Start Of Image.
+const SOS: u16 = 0xffda; // Start Of Scan.
+const TEM: u16 = 0xff01; // Temporary marker, no length field.
+const RST0: u16 = 0xffd0; // Restart markers RST0..RST7, no length field.
+const RST7: u16 = 0xffd7;
+const APP1: u16 = 0xffe1; // APP1 marker.
+const EXIF_ID: &[u8; 6] = b"Exif\0\0"; // Identifier of the Exif APP1 segment.
+const MAX_SEGMENT: u16 = 0xffff - 2; // Longest segment payload.
+const GPS: u16 = 0x8825; // GPS data.
+const EXIF_IFD: u16 = 0x8769; // Exif sub-IFD.
+const DATE_TIME_ORIGINAL: u16 = 0x9003; // When the photo was taken, local time.
+
+// IFD entry types of interest.
+const BYTE: u16 = 1;
+const ASCII: u16 = 2;
+const SHORT: u16 = 3;
+const LONG: u16 = 4;
+const RATIONAL: u16 = 5;
+const UNDEFINED: u16 = 7;
+const SRATIONAL: u16 = 10;
+const LONG8: u16 = 16; // BigTIFF only.
+const IFD8: u16 = 18; // BigTIFF only.
+
+// GPS directory tags of interest.
+const GPS_VERSION: u16 = 0; // Version of the GPS IFD.
+const LAT_Q: u16 = 1; // Latitude quadrant.
+const LAT_V: u16 = 2; // Latitude value.
+const LONG_Q: u16 = 3; // Longtitude quadrant.
+const LONG_V: u16 = 4; // Longtitude value;
+const ALT_REF: u16 = 5; // Altitude reference, 1 for below sea level.
+const ALTITUDE: u16 = 6; // Altitude in meters.
+const TIMESTAMP: u16 = 7; // GPS timestamp.
+const SATELLITES: u16 = 8; // Satellites used for the fix.
+const DOP: u16 = 0x0b; // Dilution of precision of the fix.
+const SPEED_REF: u16 = 0x0c; // Speed unit, K, M or N.
+const SPEED: u16 = 0x0d; // Speed of the receiver.
+
+// Directions are used as is, even though GPX course is relative to true north
+// and the photo might be reporting magnetic direction (tags 0x0e and 0x10).
+const TRACK: u16 = 0x0f; // Direction of movement.
+const IMG_DIRECTION: u16 = 0x11; // Direction the camera was pointing at.
+const MAP_DATUM: u16 = 0x12; // Geodetic datum of the coordinates.
+const DEST_LAT_Q: u16 = 0x13; // Destination latitude quadrant.
+const DEST_LAT_V: u16 = 0x14; // Destination latitude value.
+const DEST_LONG_Q: u16 = 0x15; // Destination longtitude quadrant.
+const DEST_LONG_V: u16 = 0x16; // Destination longtitude value.
+const AREA_INFO: u16 = 0x1c; // Name of the GPS area.
+const DATESTAMP: u16 = 0x1d; // GPS Date.
+const DIFFERENTIAL: u16 = 0x1e; // Differential correction applied.
+const H_ERROR: u16 = 0x1f; // Horizontal positioning error in meters.
+
+// Exif names of the tags above, listed in the help.
+const GPS_TAG_NAMES: &[(u16, &str)] = &[
+    (GPS_VERSION, "GPSVersionID"),
+    (LAT_Q, "GPSLatitudeRef"),
+    (LAT_V, "GPSLatitude"),
+    (LONG_Q, "GPSLongitudeRef"),
+    (LONG_V, "GPSLongitude"),
+    (ALT_REF, "GPSAltitudeRef"),
+    (ALTITUDE, "GPSAltitude"),
+    (TIMESTAMP, "GPSTimeStamp"),
+    (SATELLITES, "GPSSatellites"),
+    (DOP, "GPSDOP"),
+    (SPEED_REF, "GPSSpeedRef"),
+    (SPEED, "GPSSpeed"),
+    (TRACK, "GPSTrack"),
+    (IMG_DIRECTION, "GPSImgDirection"),
+    (MAP_DATUM, "GPSMapDatum"),
+    (DEST_LAT_Q, "GPSDestLatitudeRef"),
+    (DEST_LAT_V, "GPSDestLatitude"),
+    (DEST_LONG_Q, "GPSDestLongitudeRef"),
+    (DEST_LONG_V, "GPSDestLongitude"),
+    (AREA_INFO, "GPSAreaInformation"),
+    (DATESTAMP, "GPSDateStamp"),
+    (DIFFERENTIAL, "GPSDifferential"),
+    (H_ERROR, "GPSHPositioningError"),
+];
+
+// Coordinates are required, timestamp and datestamp are optional but are
+// only used when both are present.
+const NUM_ESSENTIAL_ENTRIES: usize = 4;
+const NUM_TIME_ENTRIES: usize = 2;
+
+// The generated output, grows with the number of waypoints.
+type AV = Vec<u8>;
+
+// Size in bytes of a single value of the given IFD entry type.
+fn type_size(typ_e: u16) -> usize {
+    match typ_e {
+        3 | 8 => 2,
+        4 | 9 | 11 => 4,
+        5 | 10 | 12 | 16..=18 => 8,
+        _ => 1,
+    }
+}
+
+// Retrieve the raw bytes of an IFD entry value. Values of up to four bytes
+// are stored in the offset field itself. The cursor is left in place even if
+// the value does not fit into the buffer, so that the following entries can
+// still be read.
+fn value_bytes(buf: &mut BufReader, entry: &IfdEntry) -> Result<Vec<u8>> {
+    let size = (entry.count as usize).saturating_mul(type_size(entry.typ_e));
+
+    if size <= 4 {
+        return Ok(buf.u32_to(entry.offset)[..size].to_vec());
+    }
+    let end = (entry.offset as usize).saturating_add(size);
+    if end > buf.buffer.len() {
+        return Err(Error::from(ErrorKind::UnexpectedEof));
+    }
+
+    let mut bytes = vec![0u8; size];
+    buf.save_cursor();
+    let read = buf
+        .set_cursor(entry.offset as usize)
+        .and_then(|_| buf.read_exact(&mut bytes));
+    buf.restore_cursor();
+    read.map(|_| bytes)
+}
+
+// Retrieve the ASCII value of an IFD entry.
+fn string_from_ifd(buf: &mut BufReader, entry: &IfdEntry) -> Result<String> {
+    let bytes = value_bytes(buf, entry)?;
+    let text = String::from_utf8_lossy(&bytes);
+    Ok(text.trim_end_matches(char::from(0)).trim().to_string())
+}
+
+// Decoded value of an IFD entry, rationals are converted to floats. This is
+// what the parse_file_with() callback is given.
+pub enum TagValue {
+    Bytes(Vec<u8>), // BYTE and UNDEFINED values.
+    Ascii(String),
+    Short(Vec<u16>),
+    Long(Vec<u32>),
+    Rational(Vec<f64>),
+    SRational(Vec<f64>),
+    Other, // Value of another type, or of a tag not decoded.
+}
+
+impl TagValue {
+    pub fn count(&self) -> usize {
+        match self {
+            TagValue::Bytes(v) => v.len(),
+            TagValue::Ascii(v) => v.len(),
+            TagValue::Short(v) => v.len(),
+            TagValue::Long(v) => v.len(),
+            TagValue::Rational(v) | TagValue::SRational(v) => v.len(),
+            TagValue::Other => 0,
+        }
+    }
+
+    pub fn text(&self) -> Option<&str> {
+        match self {
+            TagValue::Ascii(text) => Some(text),
+            _ => None,
+        }
+    }
+
+    // First value of an integer entry.
+    pub fn integer(&self) -> Option<u32> {
+        match self {
+            TagValue::Bytes(v) => v.first().map(|&v| u32::from(v)),
+            TagValue::Short(v) => v.first().map(|&v| u32::from(v)),
+            TagValue::Long(v) => v.first().copied(),
+            _ => None,
+        }
+    }
+
+    pub fn rationals(&self) -> &[f64] {
+        match self {
+            TagValue::Rational(v) | TagValue::SRational(v) => v,
+            _ => &[],
+        }
+    }
+
+    pub fn rational(&self) -> Option<f64> {
+        self.rationals().first().copied()
+    }
+}
+
+// Callback getting the tag and the value of the Exif entries.
+type TagHook<'a> = &'a mut dyn FnMut(u16, &TagValue);
+
+// Decode the value of an IFD entry according to its type.
+fn read_value(buf: &mut BufReader, entry: &IfdEntry) -> Result<TagValue> {
+    let typ_e = entry.typ_e;
+    let bytes = match typ_e {
+        ASCII => return Ok(TagValue::Ascii(string_from_ifd(buf, entry)?)),
+        BYTE | UNDEFINED | SHORT | LONG | RATIONAL | SRATIONAL => value_bytes(buf, entry)?,
+        _ => return Ok(TagValue::Other),
+    };
+    let word = |c: &[u8]| buf.u32_from([c[0], c[1], c[2], c[3]]);
+    let chunks = bytes.chunks_exact(type_size(typ_e));
+
+    Ok(match typ_e {
+        SHORT => TagValue::Short(chunks.map(|c| buf.u16_from([c[0], c[1]])).collect()),
+        LONG => TagValue::Long(chunks.map(word).collect()),
+        RATIONAL => TagValue::Rational(
+            chunks
+                .map(|c| word(c) as f64 / word(&c[4..]) as f64)
+                .collect(),
+        ),
+        SRATIONAL => TagValue::SRational(
+            chunks
+                .map(|c| word(c) as i32 as f64 / word(&c[4..]) as i32 as f64)
+                .collect(),
+        ),
+        _ => TagValue::Bytes(bytes),
+    })
+}
+
+// Entries of the GPS IFD at the cursor position. Only the values of the tags
+// listed in GPS_TAG_NAMES are decoded, the rest are reported as Other.
+struct GpsIfd<'a> {
+    buf: &'a mut BufReader,
+    name: &'a str, // Of the file, for the warnings.
+    remaining: u64,
+}
+
+impl<'a> GpsIfd<'a> {
+    pub fn new(buf: &'a mut BufReader, name: &'a str) -> Result<Self> {
+        let remaining = read_entry_count(buf)?;
+        Ok(GpsIfd {
+            buf,
+            name,
+            remaining,
+        })
+    }
+}
+
+impl Iterator for GpsIfd<'_> {
+    type Item = Result<(u16, TagValue)>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        while self.remaining > 0 {
+            self.remaining -= 1;
+
+            let entry = match read_entry(self.buf) {
+                Ok(entry) => entry,
+                Err(e) => {
+                    self.remaining = 0;
+                    return Some(Err(e));
+                }
+            };
+            let tag = entry.tag;
+            if !GPS_TAG_NAMES.iter().any(|(t, _)| *t == tag) {
+                return Some(Ok((tag, TagValue::Other)));
+            }
+            // An entry with a corrupt offset is skipped, so that only the
+            // field it carries is lost.
+            match read_value(self.buf, &entry) {
+                Ok(value) => return Some(Ok((tag, value))),
+                Err(e) if e.kind() == ErrorKind::UnexpectedEof => eprintln!(
+                    "Warning: {} GPS entry {:#06x} points outside the Exif data, ignored",
+                    self.name, tag
+                ),
+                Err(e) => return Some(Err(e)),
+            }
+        }
+        None
+    }
+}
+
+// GPSAreaInformation is UNDEFINED, starting with an eight byte character code.
+// Unicode text is UCS-2 in the byte order of the file, anything else is read
+// as UTF-8.
+fn area_text(value: &TagValue, big_endian: bool) -> String {
+    let bytes = match value {
+        TagValue::Ascii(text) => return text.clone(),
+        TagValue::Bytes(bytes) if bytes.len() >= 8 => bytes,
+        _ => return String::new(),
+    };
+    let (code, text) = bytes.split_at(8);
+    let text = if code == b"UNICODE\0" {
+        let units: Vec<u16> = text
+            .chunks_exact(2)
+            .map(|c| {
+                if big_endian {
+                    u16::from_be_bytes([c[0], c[1]])
+                } else {
+                    u16::from_le_bytes([c[0], c[1]])
+                }
+            })
+            .collect();
+        String::from_utf16_lossy(&units)
+    } else {
+        String::from_utf8_lossy(text).to_string()
+    };
+    text.trim_end_matches(char::from(0)).trim().to_string()
+}
+
+// GPSSatellites is free form text, cameras put either the number of
+// satellites or the list of their IDs there. Any other content is treated as
+// unknown.
+fn count_satellites(text: &str) -> Option<u32> {
+    if let Ok(count) = text.parse() {
+        return Some(count);
+    }
+
+    let ids: Vec<&str> = text
+        .split(|c: char| c == ',' || c.is_whitespace())
+        .filter(|id| !id.is_empty())
+        .collect();
+    if !ids.is_empty() && ids.iter().all(|id| id.parse::<u32>().is_ok()) {
+        Some(ids.len() as u32)
+    } else {
+        None
+    }
+}
+
+// All GPS IFD versions published so far (2.0.0.0 to 2.4.0.0) interpret the
+// tags of interest the same way, warn if the photo claims a different major
+// version.
+fn check_gps_version(value: &TagValue, name: &str) -> [u8; 4] {
+    let mut version = [0u8; 4];
+
+    if let TagValue::Bytes(bytes) = value {
+        for (v, b) in version.iter_mut().zip(bytes) {
+            *v = *b;
+        }
+    }
+    if version[0] != 2 {
+        eprintln!(
+            "Warning: {} uses unknown GPS IFD version {}.{}.{}.{}",
+            name, version[0], version[1], version[2], version[3]
+        );
+    }
+    version
+}
+
+// GPX coordinates are always WGS-84, warn if the photo claims otherwise.
+fn check_map_datum(datum: &str, name: &str) {
+    match datum.to_uppercase().as_str() {
+        "" | "WGS-84" | "WGS84" | "WGS 84" => {}
+        _ => eprintln!(
+            "Warning: {} uses map datum \"{}\", its location could be off",
+            name, datum
+        ),
+    }
+}
+
+// Reasons for skipping an input file. These are reported as ErrorKind::Other
+// errors, which distinguishes them from I/O errors.
+#[derive(Debug)]
+enum Skip {
+    NotImage,            // Not a JPEG file, or a file without Exif.
+    NoGps,               // No Exif section or no GPS IFD in it.
+    BadHeader,           // Unsupported TIFF header of the Exif section.
+    Incomplete,          // Some of the essential GPS entries are missing.
+    BadCount(u16, u32),  // GPS entry tag and its unexpected number of values.
+    BadType(u16),        // GPS entry tag with values of an unexpected type.
+    BadLocation(String), // Unsupported or out of range location.
+    BadRef(u16),         // GPS entry tag with an invalid N/S or E/W reference.
+    Truncated,           // The file ends among the JPEG markers.
+    Damaged(String),     // Lengths or offsets pointing outside the data.
+}
+
+impl fmt::Display for Skip {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let text = match self {
+            Skip::NotImage => "does not seem to be a photo image file",
+            Skip::NoGps => "no GPS data found",
+            Skip::BadHeader => "bad Exif header",
+            Skip::Incomplete => "missing essential GPS entry/ies",
+            Skip::Truncated => "truncated before the image data",
+            Skip::BadCount(tag, count) => {
+                return write!(f, "GPS entry {:#06x} has {} value(s)", tag, count);
+            }
+            Skip::BadType(tag) => {
+                return write!(f, "GPS entry {:#06x} has values of unexpected type", tag);
+            }
+            Skip::BadLocation(location) => {
+                return write!(f, "unsupported location {}", location);
+            }
+            Skip::BadRef(tag) => {
+                return write!(f, "GPS entry {:#06x} has an invalid reference", tag);
+            }
+            Skip::Damaged(why) => return write!(f, "damaged file: {}", why),
+        };
+        write!(f, "{}", text)
+    }
+}
+
+impl std::error::Error for Skip {}
+
+impl From<Skip> for Error {
+    fn from(skip: Skip) -> Self {
+        Error::other(skip)
+    }
+}
+
+pub struct GpsInfo {
+    file_name: String,
+    lat: f64,
+    longt: f64,
+    alt: Option<f64>,     // Meters above sea level.
+    time: Option<u64>,    // Seconds since Jan 1 1970, UTC.
+    h_error: Option<f64>, // Meters, if reported by the camera.
+    gps_version: Option<[u8; 4]>,
+    satellites: Option<u32>,
+    dop: Option<f64>,
+    differential: Option<bool>,
+    speed: Option<f64>,             // Meters per second.
+    track: Option<f64>,             // Direction of movement, degrees.
+    img_direction: Option<f64>,     // Direction the camera was pointing at, degrees.
+    dest: Option<(f64, f64)>,       // Latitude and longtitude of the destination.
+    area: Option<String>,           // Name of the GPS area.
+    link: Option<(String, String)>, // Href and text of the link to the photo.
+    seq: usize,                     // Position in the input, breaks sorting ties.
+}
+
+impl fmt::Display for GpsInfo {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "file: {} {} {} {:?}",
+            self.file_name, self.lat, self.longt, self.time
+        )
+    }
+}
+
+// Number of seconds from Jan 1 1970 to the beginning of the given date.
+fn date_to_seconds(year: u64, month: u64, day: u64) -> Result<u64> {
+    if year < 1970 || month == 0 || month > 12 || day == 0 || day > days_in_month(year, month) {
+        eprintln!("invalid date {}:{}:{}", year, month, day);
+        return Err(Error::from(ErrorKind::InvalidData));
+    }
+
+    Ok(days_from_civil(year as i64, month, day) as u64 * 24 * 60 * 60)
+}
+
+fn days_in_month(year: u64, month: u64) -> u64 {
+    let (next_year, next_month) = if month == 12 {
+        (year + 1, 1)
+    } else {
+        (year, month + 1)
+    };
+
+    (days_from_civil(next_year as i64, next_month, 1) - days_from_civil(year as i64, month, 1))
+        as u64
+}
+
+fn get_num(bytes: &[u8]) -> Result<u64> {
+    let the_string = match str::from_utf8(bytes) {
+        Ok(v) => v,
+        Err(_) => {
+            eprintln!("failed to convert to string {:?}", bytes);
+            return Err(Error::from(ErrorKind::InvalidData));
+        }
+    };
+    let the_number: u64 = match the_string.parse() {
+        Ok(v) => v,
+        Err(_) => {
+            eprintln!("failed to convert to number {}", the_string);
+            return Err(Error::from(ErrorKind::InvalidData));
+        }
+    };
+
+    Ok(the_number)
+}
+
+fn to_dms(degrees: f64, hemisphere: char) -> (u32, u32, f64, char) {
+    let seconds = degrees.abs() * 3600.0;
+    let minutes = (seconds / 60.0).floor();
+
+    (
+        (minutes / 60.0).floor() as u32,
+        (minutes % 60.0) as u32,
+        seconds % 60.0,
+        hemisphere,
+    )
+}
+
+// Format DMS components as 37°46'29.64"N. Seconds are rounded to hundredths,
+// carrying into minutes and degrees as needed.
+fn format_dms(dms: (u32, u32, f64, char)) -> String {
+    let (mut degrees, mut minutes, seconds, hemisphere) = dms;
+    let mut hundredths = (seconds * 100.0).round() as u32;
+
+    if hundredths >= 6000 {
+        hundredths -= 6000;
+        minutes += 1;
+    }
+    if minutes >= 60 {
+        minutes -= 60;
+        degrees += 1;
+    }
+    format!(
+        "{}°{:02}'{:02}.{:02}\"{}",
+        degrees,
+        minutes,
+        hundredths / 100,
+        hundredths % 100,
+        hemisphere
+    )
+}
+
+const EARTH_RADIUS: f64 = 6_371_000.0; // Mean Earth radius in meters.
+const UERE: f64 = 5.0; // Typical GPS user equivalent range error in meters.
+
+impl GpsInfo {
+    fn new() -> Self {
+        Self {
+            file_name: "".to_string(),
+            lat: 0.0,
+            longt: 0.0,
+            alt: None,
+            time: None,
+            h_error: None,
+            gps_version: None,
+            satellites: None,
+            dop: None,
+            differential: None,
+            speed: None,
+            track: None,
+            img_direction: None,
+            dest: None,
+            area: None,
+            link: None,
+            seq: 0,
+        }
+    }
+
+    fn process_timestamp(&mut self, hms: &[f64]) {
+        self.add_seconds((hms[0] * 3600.0 + hms[1] * 60.0 + hms[2]) as u64);
+    }
+
+    fn process_datestamp(&mut self, date: &str) -> Result<()> {
+        // Date is expressed in form "YYYY:MM:DD"
+        let date = date.as_bytes();
+        if date.len() < 10 {
+            return Err(Error::from(ErrorKind::InvalidData));
+        }
+
+        let year = get_num(&date[0..4])?;
+        let month = get_num(&date[5..7])?;
+        let day = get_num(&date[8..10])?;
+
+        self.add_seconds(date_to_seconds(year, month, day)?);
+        Ok(())
+    }
+
+    fn add_seconds(&mut self, seconds: u64) {
+        self.time = Some(self.time.unwrap_or(0).saturating_add(seconds));
+    }
+
+    // Estimated horizontal error of the fix in meters: the positioning error
+    // when reported, otherwise derived from the dilution of precision.
+    fn quality(&self) -> Option<f64> {
+        self.h_error.or_else(|| self.dop.map(|dop| dop * UERE))
+    }
+
+    // Whether the trackpoint of the waypoint carries GPX 1.1 extensions.
+    fn has_extensions(&self) -> bool {
+        self.speed.is_some() || self.course().is_some() || self.differential.is_some()
+    }
+
+    // Course reported in the GPX output: the direction of movement when
+    // available, the direction the camera was pointing at otherwise.
+    pub fn course(&self) -> Option<f64> {
+        self.track.or(self.img_direction)
+    }
+
+    // Latitude as degrees, minutes, seconds and the hemisphere letter, the way
+    // it is stored in Exif.
+    pub fn lat_dms(&self) -> (u32, u32, f64, char) {
+        to_dms(self.lat, if self.lat < 0.0 { 'S' } else { 'N' })
+    }
+
+    pub fn lon_dms(&self) -> (u32, u32, f64, char) {
+        to_dms(self.longt, if self.longt < 0.0 { 'W' } else { 'E' })
+    }
+
+    // Move the waypoint to the center of the grid cell of the given size it
+    // falls into. Cells are `meters` high, their width in degrees grows with
+    // latitude to keep them roughly square.
+    fn snap_to_grid(&mut self, meters: f64) {
+        let lat_step = (meters / EARTH_RADIUS).to_degrees();
+        self.lat = ((self.lat / lat_step).round() * lat_step).clamp(-90.0, 90.0);
+
+        let long_step = (lat_step / self.lat.to_radians().cos()).min(360.0);
+        self.longt = (self.longt / long_step).round() * long_step;
+    }
+
+    // Great circle distance to the other waypoint in meters, using the
+    // haversine formula.
+    pub fn distance_from(&self, other: &GpsInfo) -> f64 {
+        let lat1 = self.lat.to_radians();
+        let lat2 = other.lat.to_radians();
+        let dlat = lat2 - lat1;
+        let dlong = (other.longt - self.longt).to_radians();
+
+        let a = (dlat / 2.0).sin().powi(2) + lat1.cos() * lat2.cos() * (dlong / 2.0).sin().powi(2);
+        let c = 2.0 * a.sqrt().atan2((1.0 - a).sqrt());
+
+        EARTH_RADIUS * c
+    }
+
+    // Equirectangular approximation of distance_from(), much cheaper and
+    // accurate to well under a meter over the few meters the duplicate
+    // checks are interested in. Distances of hundreds of kilometers come out
+    // only roughly right.
+    pub fn distance_from_fast(&self, other: &GpsInfo) -> f64 {
+        let mean_lat = ((self.lat + other.lat) / 2.0).to_radians();
+        let x = wrap_longitude(other.longt - self.longt).to_radians() * mean_lat.cos();
+        let y = (other.lat - self.lat).to_radians();
+
+        EARTH_RADIUS * x.hypot(y)
+    }
+}
+
+struct ExifBody {
+    tiff: u16,
+    size: u16,
+    offset: u32,
+}
+
+struct IfdEntry {
+    tag: u16,
+    typ_e: u16,
+    count: u32,
+    offset: u32,
+}
+
+struct BigIfdEntry {
+    tag: u16,
+    typ_e: u16,
+    count: u64,
+    value: [u8; 8], // Offset or the value itself, in the file byte order.
+}
+
+// Structures laid out in the TIFF data as is. The fields are decoded as
+// little endian, the callers swap them for big endian data.
+trait FromBytes: Sized {
+    const SIZE: usize;
+
+    fn from_bytes(bytes: &[u8]) -> Self;
+}
+
+fn le_u16(bytes: &[u8], at: usize) -> u16 {
+    u16::from_le_bytes([bytes[at], bytes[at + 1]])
+}
+
+fn le_u32(bytes: &[u8], at: usize) -> u32 {
+    u32::from_le_bytes([bytes[at], bytes[at + 1], bytes[at + 2], bytes[at + 3]])
+}
+
+impl FromBytes for ExifBody {
+    const SIZE: usize = 8;
+
+    fn from_bytes(bytes: &[u8]) -> Self {
+        ExifBody {
+            tiff: le_u16(bytes, 0),
+            size: le_u16(bytes, 2),
+            offset: le_u32(bytes, 4),
+        }
+    }
+}
+
+impl FromBytes for IfdEntry {
+    const SIZE: usize = 12;
+
+    fn from_bytes(bytes: &[u8]) -> Self {
+        IfdEntry {
+            tag: le_u16(bytes, 0),
+            typ_e: le_u16(bytes, 2),
+            count: le_u32(bytes, 4),
+            offset: le_u32(bytes, 8),
+        }
+    }
+}
+
+impl FromBytes for BigIfdEntry {
+    const SIZE: usize = 20;
+
+    fn from_bytes(bytes: &[u8]) -> Self {
+        let (mut count, mut value) = ([0u8; 8], [0u8; 8]);
+
+        count.copy_from_slice(&bytes[4..12]);
+        value.copy_from_slice(&bytes[12..20]);
+        BigIfdEntry {
+            tag: le_u16(bytes, 0),
+            typ_e: le_u16(bytes, 2),
+            count: u64::from_le_bytes(count),
+            value,
+        }
+    }
+}
+
+struct BufReader {
+    cursor_stack: Vec<usize>,
+    cursor: usize,
+    buffer: Vec<u8>,
+    big_endian: bool, // Byte order of the TIFF structure in the buffer.
+    big_tiff: bool,   // BigTIFF, with 64-bit offsets and counts.
+}
+
+impl BufReader {
+    pub fn init<R: Read>(&mut self, f: &mut R, size: usize) -> Result<()> {
+        self.buffer = vec![0u8; size];
+        f.read_exact(&mut self.buffer)
+    }
+
+    #[allow(dead_code)]
+    pub fn dump(&self, num: usize) {
+        let mut i: usize = 0;
+
+        while i < num {
+            eprint!(" {:02x}", self.buffer[self.cursor + i]);
+            i += 1;
+        }
+        eprintln!();
+    }
+
+    pub fn set_cursor(&mut self, new_cursor: usize) -> Result<()> {
+        if new_cursor >= self.buffer.len() {
+            Err(Error::from(ErrorKind::UnexpectedEof))
+        } else {
+            self.cursor = new_cursor;
+            Ok(())
+        }
+    }
+
+    pub fn save_cursor(&mut self) {
+        self.cursor_stack.push(self.cursor);
+    }
+
+    pub fn restore_cursor(&mut self) {
+        self.cursor = self.cursor_stack.pop().expect("cursor stack is empty!");
+    }
+
+    // Conversions between bytes in the buffer's byte order and numbers.
+    pub fn u16_from(&self, bytes: [u8; 2]) -> u16 {
+        if self.big_endian {
+            u16::from_be_bytes(bytes)
+        } else {
+            u16::from_le_bytes(bytes)
+        }
+    }
+
+    pub fn u32_from(&self, bytes: [u8; 4]) -> u32 {
+        if self.big_endian {
+            u32::from_be_bytes(bytes)
+        } else {
+            u32::from_le_bytes(bytes)
+        }
+    }
+
+    pub fn u64_from(&self, bytes: [u8; 8]) -> u64 {
+        if self.big_endian {
+            u64::from_be_bytes(bytes)
+        } else {
+            u64::from_le_bytes(bytes)
+        }
+    }
+
+    pub fn u32_to(&self, value: u32) -> [u8; 4] {
+        if self.big_endian {
+            value.to_be_bytes()
+        } else {
+            value.to_le_bytes()
+        }
+    }
+}
+
+// Reads follow the Read contract: as much as is left in the buffer, nothing
+// past its end. read_exact() is all or nothing, the cursor does not move if
+// there is not enough data; this is what the TIFF structures are read with.
+impl Read for BufReader {
+    fn read(&mut self, buf: &mut [u8]) -> Result<usize> {
+        let start = self.cursor.min(self.buffer.len());
+        let len = buf.len().min(self.buffer.len() - start);
+
+        buf[..len].copy_from_slice(&self.buffer[start..start + len]);
+        self.cursor += len;
+        Ok(len)
+    }
+
+    fn read_exact(&mut self, buf: &mut [u8]) -> Result<()> {
+        match self.cursor.checked_add(buf.len()) {
+            Some(end) if end <= self.buffer.len() => {
+                buf.copy_from_slice(&self.buffer[self.cursor..end]);
+                self.cursor = end;
+                Ok(())
+            }
+            _ => Err(Error::from(ErrorKind::UnexpectedEof)),
+        }
+    }
+}
+
+const INTEL: u16 = 0x4949; // "II", little endian TIFF.
+const MOTOROLA: u16 = 0x4d4d; // "MM", big endian TIFF.
+const BIG_TIFF: u16 = 43; // Version of BigTIFF, 42 for classic TIFF.
+
+impl ExifBody {
+    fn is_valid(&self) -> bool {
+        let ifd0 = if self.size == BIG_TIFF { 16 } else { 8 };
+        (self.tiff == INTEL || self.tiff == MOTOROLA) && self.offset == ifd0
+    }
+}
+
+// Read the TIFF header and set the byte order of the buffer accordingly.
+fn read_header(buf: &mut BufReader) -> Result<ExifBody> {
+    let mut eb = read_struct::<ExifBody, BufReader>(buf)?;
+
+    buf.big_endian = eb.tiff == MOTOROLA;
+    if buf.big_endian {
+        eb.size = eb.size.swap_bytes();
+        eb.offset = eb.offset.swap_bytes();
+    }
+
+    // In the BigTIFF header the version is followed by the offset size, which
+    // must be 8, two reserved bytes and a 64-bit offset of IFD0.
+    buf.big_tiff = eb.size == BIG_TIFF;
+    if buf.big_tiff {
+        let bytes = buf.u32_to(eb.offset);
+        let layout = (buf.u16_from([bytes[0], bytes[1]]), bytes[2], bytes[3]);
+        let offset = read_offset(buf)?;
+        eb.offset = if layout == (8, 0, 0) { offset } else { 0 };
+    }
+    Ok(eb)
+}
+
+// Offset of an IFD, 64-bit in BigTIFF. The whole file is in memory, so longer
+// offsets can not point anywhere anyway.
+fn read_offset(buf: &mut BufReader) -> Result<u32> {
+    if !buf.big_tiff {
+        let mut offset = [0u8; 4];
+        buf.read_exact(&mut offset)?;
+        return Ok(buf.u32_from(offset));
+    }
+
+    let mut offset = [0u8; 8];
+    buf.read_exact(&mut offset)?;
+    u32::try_from(buf.u64_from(offset)).map_err(|_| Error::from(ErrorKind::UnexpectedEof))
+}
+
+// Number of entries of the IFD at the cursor, 64-bit in BigTIFF.
+fn read_entry_count(buf: &mut BufReader) -> Result<u64> {
+    if !buf.big_tiff {
+        return Ok(u64::from(read_u16(buf)?));
+    }
+
+    let mut count = [0u8; 8];
+    buf.read_exact(&mut count)?;
+    Ok(buf.u64_from(count))
+}
+
+// Read an IFD entry, converting it to the native byte order. Values stored in
+// the offset field can be retrieved with value_bytes().
+fn read_entry(buf: &mut BufReader) -> Result<IfdEntry> {
+    if buf.big_tiff {
+        return read_big_entry(buf);
+    }
+
+    let mut entry = read_struct::<IfdEntry, BufReader>(buf)?;
+
+    if buf.big_endian {
+        entry.tag = entry.tag.swap_bytes();
+        entry.typ_e = entry.typ_e.swap_bytes();
+        entry.count = entry.count.swap_bytes();
+        entry.offset = entry.offset.swap_bytes();
+    }
+    Ok(entry)
+}
+
+// BigTIFF entries are converted into the classic ones. Values of up to four
+// bytes go into the offset field as usual, values of up to eight bytes, kept
+// in the entry, are pointed at where they are in the buffer. Single 64-bit
+// offsets, used for the sub-IFD pointers, become LONG values.
+fn read_big_entry(buf: &mut BufReader) -> Result<IfdEntry> {
+    let value_position = buf.cursor + 12;
+    let big = read_struct::<BigIfdEntry, BufReader>(buf)?;
+    let (mut tag, mut typ_e, mut count, value) = (big.tag, big.typ_e, big.count, big.value);
+
+    if buf.big_endian {
+        tag = tag.swap_bytes();
+        typ_e = typ_e.swap_bytes();
+        count = count.swap_bytes();
+    }
+    let too_long = || Error::from(ErrorKind::UnexpectedEof);
+    let count = u32::try_from(count).map_err(|_| too_long())?;
+    let size = count as usize * type_size(typ_e);
+    let offset = if (typ_e == LONG8 || typ_e == IFD8) && count == 1 {
+        typ_e = LONG;
+        u32::try_from(buf.u64_from(value)).map_err(|_| too_long())?
+    } else if size <= 4 {
+        buf.u32_from([value[0], value[1], value[2], value[3]])
+    } else if size <= 8 {
+        value_position as u32
+    } else {
+        u32::try_from(buf.u64_from(value)).map_err(|_| too_long())?
+    };
+    Ok(IfdEntry {
+        tag,
+        typ_e,
+        count,
+        offset,
+    })
+}
+
+impl fmt::Display for IfdEntry {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "tag: {:04x}, type: {}, count {}, offset {}",
+            self.tag, self.typ_e, self.count, self.offset
+        )
+    }
+}
+
+impl fmt::Display for ExifBody {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "tiff {:x}, size {}, offset {}",
+            self.tiff, self.size, self.offset
+        )
+    }
+}
+
+// Read a structure from the stream, a short read fails with UnexpectedEof.
+fn read_struct<T: FromBytes, R: Read>(f: &mut R) -> Result<T> {
+    let mut bytes = vec![0u8; T::SIZE];
+
+    f.read_exact(&mut bytes)?;
+    Ok(T::from_bytes(&bytes))
+}
+
+fn read_u16(buf: &mut BufReader) -> Result<u16> {
+    let mut value = [0u8; 2];
+    buf.read_exact(&mut value)?;
+    Ok(buf.u16_from(value))
+}
+
+fn read_tag<T: Read>(f: &mut T) -> Result<u16> {
+    let mut tag = [0u8; 2];
+    f.read_exact(&mut tag)?;
+    Ok(u16::from_be_bytes(tag))
+}
+
+// Make sure the entry carries the expected number of values, so that data of
+// the adjacent entries is not read instead.
+fn check_count(tag: u16, value: &TagValue, expected: usize) -> Result<()> {
+    if value.count() == expected {
+        Ok(())
+    } else {
+        Err(Skip::BadCount(tag, value.count() as u32).into())
+    }
+}
+
+// Coordinates and the time stamp have to be unsigned rationals, values of
+// other types would be misread.
+fn check_rational(tag: u16, value: &TagValue) -> Result<()> {
+    match value {
+        TagValue::Rational(_) => Ok(()),
+        _ => Err(Skip::BadType(tag).into()),
+    }
+}
+
+// Coordinates are supposed to be degrees, minutes and seconds, but some
+// encoders write a single value of decimal degrees.
+fn degrees(tag: u16, value: &TagValue) -> Result<f64> {
+    check_rational(tag, value)?;
+    match *value.rationals() {
+        [degrees, minutes, seconds] => Ok(degrees + (minutes * 60.0 + seconds) / 3600.0),
+        [degrees] => Ok(degrees),
+        _ => Err(Skip::BadCount(tag, value.count() as u32).into()),
+    }
+}
+
+// Sign of a coordinate given its reference, 'N'/'S' or 'E'/'W', None if the
+// reference is anything else.
+fn hemisphere_sign(value: &TagValue, positive: char, negative: char) -> Option<f64> {
+    match value.text()?.chars().next()? {
+        c if c == positive => Some(1.0),
+        c if c == negative => Some(-1.0),
+        _ => None,
+    }
+}
+
+// Meters per second in a unit of GPSSpeedRef, km/h when not recorded.
+const KMH: f64 = 1000.0 / 3600.0;
+
+fn speed_unit_of(text: &str) -> Option<f64> {
+    match text.chars().next()? {
+        'K' => Some(KMH),
+        'M' => Some(1609.344 / 3600.0),
+        'N' => Some(1852.0 / 3600.0),
+        _ => None,
+    }
+}
+
+// Start of the UTC day of a photo taken at `taken`, the DateTimeOriginal
+// "YYYY:MM:DD HH:MM:SS" in local time, with the GPS time of day `time_of_day`.
+// The time zone is not known, the day putting the two times closest together
+// is used, which is right for time zones up to 12 hours away from UTC.
+fn utc_day(taken: &str, time_of_day: u64) -> Option<u64> {
+    const DAY: u64 = 24 * 60 * 60;
+    let taken = taken.as_bytes();
+    let num = |from: usize, to: usize| -> Option<u64> {
+        str::from_utf8(taken.get(from..to)?).ok()?.parse().ok()
+    };
+    let (year, month, day) = (num(0, 4)?, num(5, 7)?, num(8, 10)?);
+    if year < 1970 || month == 0 || month > 12 || day == 0 || day > days_in_month(year, month) {
+        return None;
+    }
+
+    let local = days_from_civil(year as i64, month, day) as u64 * DAY
+        + num(11, 13)? * 3600
+        + num(14, 16)? * 60
+        + num(17, 19)?;
+    let local_day = local / DAY * DAY;
+    [
+        local_day.checked_sub(DAY),
+        Some(local_day),
+        Some(local_day + DAY),
+    ]
+    .iter()
+    .flatten()
+    .min_by_key(|&&d| (d + time_of_day).abs_diff(local))
+    .copied()
+}
+
+// Without the GPS datestamp `date_taken`, the DateTimeOriginal of the photo,
+// supplies the date for the GPS time of day.
+fn process_gps_section(
+    buffer: &mut BufReader,
+    name: &str,
+    date_taken: Option<&str>,
+    waypoints: &mut Vec<GpsInfo>,
+) -> Result<()> {
+    let big_endian = buffer.big_endian;
+    let mut essentials: usize = 0;
+    let mut time_entries: usize = 0;
+    let mut has_datestamp = false;
+    let mut waypoint: GpsInfo = GpsInfo::new();
+    let mut lat: f64 = 0.0;
+    let mut longt: f64 = 0.0;
+    let mut lat_sign: f64 = 1.0;
+    let mut longt_sign: f64 = 1.0;
+    let mut dest_lat: Option<f64> = None;
+    let mut dest_longt: Option<f64> = None;
+    let mut dest_lat_sign: Option<f64> = Some(1.0);
+    let mut dest_longt_sign: Option<f64> = Some(1.0);
+    let mut alt: Option<f64> = None;
+    let mut alt_sign: f64 = 1.0;
+    let mut speed: Option<f64> = None;
+    let mut speed_unit: Option<f64> = Some(KMH);
+
+    waypoint.file_name = name.to_string();
+    for item in GpsIfd::new(buffer, name)? {
+        let (tag, value) = item?;
+
+        match tag {
+            LAT_Q | LONG_Q | LAT_V | LONG_V => essentials += 1,
+            TIMESTAMP | DATESTAMP => time_entries += 1,
+            _ => {}
+        }
+        match tag {
+            LAT_Q => lat_sign = hemisphere_sign(&value, 'N', 'S').ok_or(Skip::BadRef(tag))?,
+            LONG_Q => longt_sign = hemisphere_sign(&value, 'E', 'W').ok_or(Skip::BadRef(tag))?,
+            LAT_V => lat = degrees(tag, &value)?,
+            LONG_V => longt = degrees(tag, &value)?,
+            ALT_REF => {
+                alt_sign = if value.integer() == Some(1) {
+                    -1.0
+                } else {
+                    1.0
+                }
+            }
+            ALTITUDE => alt = value.rational().filter(|a| a.is_finite()),
+            TIMESTAMP => {
+                check_rational(tag, &value)?;
+                check_count(tag, &value, 3)?;
+                waypoint.process_timestamp(value.rationals());
+            }
+            // "YYYY:MM:DD", the count of 11 includes the terminating NUL.
+            DATESTAMP => {
+                let date = value.text().ok_or(Skip::BadType(tag))?;
+                check_count(tag, &value, 10)?;
+                waypoint.process_datestamp(date)?;
+                has_datestamp = true;
+            }
+            GPS_VERSION => waypoint.gps_version = Some(check_gps_version(&value, name)),
+            MAP_DATUM => check_map_datum(value.text().unwrap_or_default(), name),
+            SATELLITES => waypoint.satellites = value.text().and_then(count_satellites),
+            DIFFERENTIAL => waypoint.differential = value.integer().map(|v| v == 1),
+            DOP => waypoint.dop = value.rational(),
+            H_ERROR => waypoint.h_error = value.rational(),
+            SPEED_REF => speed_unit = value.text().and_then(speed_unit_of),
+            SPEED => speed = value.rational().filter(|s| s.is_finite()),
+            TRACK => waypoint.track = value.rational(),
+            IMG_DIRECTION => waypoint.img_direction = value.rational(),
+            // Destination is only extra information, a bad reference
+            // drops it rather than the photo.
+            DEST_LAT_Q => dest_lat_sign = hemisphere_sign(&value, 'N', 'S'),
+            DEST_LONG_Q => dest_longt_sign = hemisphere_sign(&value, 'E', 'W'),
+            DEST_LAT_V => dest_lat = Some(degrees(tag, &value)?),
+            DEST_LONG_V => dest_longt = Some(degrees(tag, &value)?),
+            AREA_INFO => {
+                waypoint.area = Some(area_text(&value, big_endian)).filter(|a| !a.is_empty())
+            }
+            _ => {}
+        }
+    }
+    if essentials != NUM_ESSENTIAL_ENTRIES {
+        return Err(Skip::Incomplete.into());
+    }
+
+    // References can come before or after the values, so the signs are only
+    // applied once all entries were seen. Rationals with zero denominators
+    // end up out of range here.
+    waypoint.lat = lat * lat_sign;
+    waypoint.longt = longt * longt_sign;
+    if !(waypoint.lat.abs() <= 90.0 && waypoint.longt.abs() <= 180.0) {
+        let location = format!("{}, {}", waypoint.lat, waypoint.longt);
+        return Err(Skip::BadLocation(location).into());
+    }
+    if time_entries == 1 && !has_datestamp {
+        waypoint.time = waypoint
+            .time
+            .zip(date_taken)
+            .and_then(|(time, taken)| Some(utc_day(taken, time)? + time));
+    } else if time_entries != NUM_TIME_ENTRIES {
+        waypoint.time = None;
+    }
+    waypoint.alt = alt.map(|alt| alt * alt_sign);
+    // Speed is only extra information as well, an unknown unit drops it.
+    waypoint.speed = speed.zip(speed_unit).map(|(speed, unit)| speed * unit);
+    if let (Some(lat), Some(longt), Some(lat_sign), Some(longt_sign)) =
+        (dest_lat, dest_longt, dest_lat_sign, dest_longt_sign)
+    {
+        waypoint.dest = Some((lat * lat_sign, longt * longt_sign));
+    }
+
+    waypoints.push(waypoint);
+    Ok(())
+}
+
+// Process the Exif APP1 segment contents following the Exif identifier. The
+// entries are handed to `on_tag`, if given, before the GPS IFD is looked for.
+fn handle_app1(
+    buffer: &mut BufReader,
+    name: &str,
+    waypoints: &mut Vec<GpsInfo>,
+    on_tag: Option<TagHook>,
+) -> Result<()> {
+    let eb = read_header(buffer)?;
+    if !eb.is_valid() {
+        return Err(Skip::BadHeader.into());
+    }
+    if let Some(on_tag) = on_tag {
+        visit_tags(buffer, eb.offset, on_tag);
+    }
+
+    match find_gps_ifd(buffer, eb.offset, 0, &mut HashSet::new())? {
+        Some(offset) => {
+            let date_taken = find_date_taken(buffer, eb.offset);
+            buffer.set_cursor(offset as usize)?;
+            process_gps_section(buffer, name, date_taken.as_deref(), waypoints)
+        }
+        None => Err(Skip::NoGps.into()),
+    }
+}
+
+// Offset of the GPS IFD, looked for in the chain of IFDs starting at `ifd`.
+// Some cameras put the GPS pointer into IFD1 (the thumbnail directory)
+// instead of IFD0, others into the Exif sub-IFD, which is searched as well.
+// Zero next IFD offset terminates the chain, corrupted files could have it
+// loop back though, or have the sub-IFDs point at each other: no IFD is read
+// twice and the sub-IFDs are not expected to be nested deeper than IFD0 ->
+// Exif IFD.
+fn find_gps_ifd(
+    buffer: &mut BufReader,
+    mut ifd: u32,
+    depth: usize,
+    visited: &mut HashSet<u32>,
+) -> Result<Option<u32>> {
+    const MAX_IFDS: usize = 8;
+
+    while ifd != 0 && visited.len() < MAX_IFDS && visited.insert(ifd) {
+        buffer.set_cursor(ifd as usize)?;
+        let (gps, exif, next_ifd) = scan_ifd(buffer)?;
+        if gps.is_some() {
+            return Ok(gps);
+        }
+        if let Some(exif) = exif.filter(|_| depth < 1) {
+            if let Some(gps) = find_gps_ifd(buffer, exif, depth + 1, visited)? {
+                return Ok(Some(gps));
+            }
+        }
+        ifd = next_ifd;
+    }
+    Ok(None)
+}
+
+// Hand every entry of the IFD chain starting at `ifd0`, of the Exif sub-IFD
+// and of the GPS IFD to `on_tag`, with the value decoded. The walk is limited
+// the same as in find_gps_ifd(). It is only extra information, so an IFD
+// which cannot be read ends the walk of that IFD and the geotag is still
+// looked for; entries with unreadable values are left out.
+fn visit_tags(buffer: &mut BufReader, ifd0: u32, on_tag: TagHook) {
+    const MAX_IFDS: usize = 8;
+    let mut pending = vec![ifd0];
+    let mut visited: HashSet<u32> = HashSet::new();
+
+    while let Some(ifd) = pending.pop() {
+        if ifd != 0 && visited.len() < MAX_IFDS && visited.insert(ifd) {
+            let _ = visit_ifd(buffer, ifd, on_tag, &mut pending);
+        }
+    }
+}
+
+fn visit_ifd(
+    buffer: &mut BufReader,
+    ifd: u32,
+    on_tag: TagHook,
+    pending: &mut Vec<u32>,
+) -> Result<()> {
+    buffer.set_cursor(ifd as usize)?;
+    for _ in 0..read_entry_count(buffer)? {
+        let entry = read_entry(buffer)?;
+        if entry.tag == GPS || entry.tag == EXIF_IFD {
+            pending.push(entry.offset);
+        }
+        if let Ok(value) = read_value(buffer, &entry) {
+            on_tag(entry.tag, &value);
+        }
+    }
+    pending.push(read_offset(buffer)?);
+    Ok(())
+}
+
+// DateTimeOriginal of the photo, kept in IFD0 or in the Exif sub-IFD. It is
+// only a fallback for the GPS datestamp, so problems reading it are ignored.
+fn find_date_taken(buffer: &mut BufReader, ifd0: u32) -> Option<String> {
+    let mut ifd = Some(ifd0);
+
+    for _ in 0..2 {
+        buffer.set_cursor(ifd.take()? as usize).ok()?;
+        for _ in 0..read_entry_count(buffer).ok()? {
+            let entry = read_entry(buffer).ok()?;
+            match entry.tag {
+                DATE_TIME_ORIGINAL if entry.typ_e == ASCII => {
+                    return string_from_ifd(buffer, &entry).ok();
+                }
+                EXIF_IFD => ifd = Some(entry.offset),
+                _ => {}
+            }
+        }
+    }
+    None
+}
+
+// Read the IFD at the cursor, return the GPS and Exif IFD offsets, if the IFD
+// points to them, and the offset of the next IFD (zero if this is the last
+// one).
+fn scan_ifd(buffer: &mut BufReader) -> Result<(Option<u32>, Option<u32>, u32)> {
+    let num_entries = read_entry_count(buffer)?;
+    let mut gps = None;
+    let mut exif = None;
+
+    for _ in 0..num_entries {
+        let entry = read_entry(buffer)?;
+        match entry.tag {
+            GPS => gps = Some(entry.offset),
+            EXIF_IFD => exif = Some(entry.offset),
+            _ => {}
+        }
+    }
+
+    Ok((gps, exif, read_offset(buffer)?))
+}
+
+// Container formats geotags can be retrieved from.
+#[derive(Clone, Copy, PartialEq)]
+enum Format {
+    Jpeg,
+    Tiff,      // The whole file is TIFF data, as in TIFF based raw formats.
+    QuickTime, // QuickTime and MP4 videos, no Exif there.
+}
+
+impl Format {
+    const ALL: [Format; 3] = [Format::Jpeg, Format::Tiff, Format::QuickTime];
+
+    // Name of the format as given to --force-format.
+    fn name(self) -> &'static str {
+        match self {
+            Format::Jpeg => "jpeg",
+            Format::Tiff => "tiff",
+            Format::QuickTime => "mov",
+        }
+    }
+
+    fn description(self) -> &'static str {
+        match self {
+            Format::Jpeg => "JPEG photos with Exif",
+            Format::Tiff => "TIFF and BigTIFF files, TIFF based raw photos",
+            Format::QuickTime => "QuickTime and MP4 videos with ISO 6709 location",
+        }
+    }
+}
+
+// Detect the container format by the magic bytes at the start of the stream,
+// the stream is left at the start. There are no readers for PNG and WebP,
+// those are not recognized; HEIC files share the box structure with MP4 and
+// are read as videos.
+fn detect_format<R: Read + Seek>(f: &mut R) -> Result<Format> {
+    let mut magic = Vec::new();
+
+    f.take(8).read_to_end(&mut magic)?;
+    f.seek(SeekFrom::Start(0))?;
+    match magic[..] {
+        [a, b, ..] if u16::from_be_bytes([a, b]) == SOI => Ok(Format::Jpeg),
+        [b'I', b'I', 42 | 43, 0, ..] | [b'M', b'M', 0, 42 | 43, ..] => Ok(Format::Tiff),
+        [_, _, _, _, b'f', b't', b'y', b'p'] => Ok(Format::QuickTime),
+        _ => Err(Skip::NotImage.into()),
+    }
+}
+
+// Retrieve the TIFF data holding the geotags, detecting the container format
+// unless given.
+fn find_tiff<R: Read + Seek>(f: &mut R, format: Option<Format>) -> Result<BufReader> {
+    let format = match format {
+        Some(format) => format,
+        None => detect_format(f)?,
+    };
+    if format == Format::QuickTime {
+        return Err(Skip::NotImage.into());
+    }
+    if format == Format::Tiff {
+        let mut buffer = BufReader {
+            cursor_stack: Vec::new(),
+            cursor: 0,
+            buffer: Vec::new(),
+            big_endian: false,
+            big_tiff: false,
+        };
+
+        f.read_to_end(&mut buffer.buffer)?;
+        return Ok(buffer);
+    }
+
+    // SOI, possibly damaged if the format is forced. A file ending before the
+    // image data is skipped like one without Exif.
+    read_tag(f)?;
+    match find_exif(f) {
+        Err(e) if e.kind() == ErrorKind::UnexpectedEof => Err(Skip::Truncated.into()),
+        r => r,
+    }
+}
+
+// Walk the JPEG markers following SOI looking for the Exif APP1 segment. APP0
+// (JFIF) and other APPn segments, including APP1 segments carrying something
+// other than Exif (XMP, for instance), are skipped using their length fields.
+// Returns the contents of the Exif segment following the Exif identifier.
+fn find_exif<R: Read + Seek>(f: &mut R) -> Result<BufReader> {
+    loop {
+        let t = read_tag(f)?;
+
+        if t == SOS {
+            break;
+        }
+        // Standalone markers are not followed by a length.
+        if t == TEM || (RST0..=RST7).contains(&t) {
+            continue;
+        }
+        // The length includes the length field itself.
+        let len = read_tag(f)?
+            .checked_sub(2)
+            .ok_or_else(|| Skip::Damaged("JPEG segment length below 2".to_string()))?;
+
+        if t == APP1 && len as usize >= EXIF_ID.len() {
+            let mut id = [0u8; 6];
+
+            f.read_exact(&mut id)?;
+            let rest = len - id.len() as u16;
+            if &id == EXIF_ID {
+                let mut buffer = BufReader {
+                    cursor_stack: Vec::new(),
+                    cursor: 0,
+                    buffer: Vec::new(),
+                    big_endian: false,
+                    big_tiff: false,
+                };
+
+                buffer.init(f, rest as usize)?;
+                // Exif data which does not fit into one segment continues
+                // in the following ones, a truncated file simply ends it.
+                // The stream is left at the first segment which is not a
+                // continuation, the search can be resumed from there.
+                let mut full = len == MAX_SEGMENT;
+                while full {
+                    let pos = f.stream_position()?;
+                    match exif_continuation(f) {
+                        Ok(Some(mut more)) => {
+                            full = more.len() + EXIF_ID.len() == MAX_SEGMENT as usize;
+                            buffer.buffer.append(&mut more);
+                        }
+                        _ => {
+                            f.seek(SeekFrom::Start(pos))?;
+                            break;
+                        }
+                    }
+                }
+                return Ok(buffer);
+            }
+            f.seek(SeekFrom::Current(i64::from(rest)))?;
+            continue;
+        }
+        f.seek(SeekFrom::Current(i64::from(len)))?;
+    }
+    Err(Skip::NoGps.into())
+}
+
+// Contents of the next segment if it is a continuation of the Exif data: an
+// APP1 segment with the Exif identifier not followed by a TIFF header.
+fn exif_continuation<R: Read + Seek>(f: &mut R) -> Result<Option<Vec<u8>>> {
+    if read_tag(f)? != APP1 {
+        return Ok(None);
+    }
+    let len = read_tag(f)?.saturating_sub(2) as usize;
+    if len < EXIF_ID.len() + 4 {
+        return Ok(None);
+    }
+
+    let mut part = vec![0u8; len];
+    f.read_exact(&mut part)?;
+    let tiff = &part[EXIF_ID.len()..EXIF_ID.len() + 4];
+    if &part[..EXIF_ID.len()] != EXIF_ID || tiff == b"II*\0" || tiff == b"MM\0*" {
+        return Ok(None);
+    }
+    Ok(Some(part.split_off(EXIF_ID.len())))
+}
+
+// Name "-" stands for the image bytes piped through stdin, which is read into
+// memory in full by read_input() as stdin is not seekable.
+fn read_exif(path: &Path, format: Option<Format>) -> Result<BufReader> {
+    if path == Path::new("-") {
+        return find_tiff(&mut Cursor::new(read_input(path)?), format);
+    }
+    find_tiff(&mut File::open(path)?, format)
+}
+
+// The location of a video, with the creation time of the video as the time.
+fn parse_movie<R: Read + Seek>(f: &mut R, name: &str, waypoints: &mut Vec<GpsInfo>) -> Result<()> {
+    let movie = quicktime::read_movie(f)?;
+    let location = movie.location.ok_or(Skip::NoGps)?;
+    let (lat, longt, alt) =
+        quicktime::parse_iso6709(&location).ok_or(Skip::BadLocation(location.clone()))?;
+    let mut waypoint = GpsInfo {
+        file_name: name.to_string(),
+        lat,
+        longt,
+        alt,
+        ..GpsInfo::new()
+    };
+
+    if let Some(created) = movie.created {
+        waypoint.time = Some(created);
+    }
+    waypoints.push(waypoint);
+    Ok(())
+}
+
+// Retrieve geotags of a photo or a video, detecting the format unless given.
+fn parse_stream<R: Read + Seek>(
+    f: &mut R,
+    name: &str,
+    waypoints: &mut Vec<GpsInfo>,
+    format: Option<Format>,
+) -> Result<()> {
+    parse_stream_with(f, name, waypoints, format, None)
+}
+
+// parse_stream() handing the Exif entries to `on_tag` as well, see
+// handle_app1().
+fn parse_stream_with<R: Read + Seek>(
+    f: &mut R,
+    name: &str,
+    waypoints: &mut Vec<GpsInfo>,
+    format: Option<Format>,
+    mut on_tag: Option<TagHook>,
+) -> Result<()> {
+    let format = match format {
+        Some(format) => format,
+        None => detect_format(f)?,
+    };
+    if format == Format::QuickTime {
+        return parse_movie(f, name, waypoints);
+    }
+
+    let mut buffer = find_tiff(f, Some(format))?;
+    // A JPEG can carry more than one Exif segment, one without the GPS data
+    // does not mean there is none in the following ones.
+    loop {
+        let on_tag = on_tag.as_mut().map(|f| &mut **f as TagHook);
+        match handle_app1(&mut buffer, name, waypoints, on_tag) {
+            Err(e) if format == Format::Jpeg && no_gps_section(&e) => match find_exif(f) {
+                Ok(next) => buffer = next,
+                Err(_) => return Err(e),
+            },
+            r => return r,
+        }
+    }
+}
+
+// Whether the Exif segment was read fine but holds no GPS data.
+fn no_gps_section(e: &Error) -> bool {
+    let skip = e.get_ref().and_then(|e| e.downcast_ref::<Skip>());
+    matches!(skip, Some(Skip::NoGps | Skip::BadHeader | Skip::Incomplete))
+}
+
+// Retrieve geotags of an image held in memory. This does not touch the file
+// system or the environment, `name` is only used to label the waypoints.
+fn parse_bytes(bytes: &[u8], name: &str, format: Option<Format>) -> Result<Vec<GpsInfo>> {
+    let mut waypoints: Vec<GpsInfo> = Vec::new();
+
+    parse_stream(&mut Cursor::new(bytes), name, &mut waypoints, format).map_err(damaged)?;
+    Ok(waypoints)
+}
+
+// Paths are only converted to text to name the waypoints, the file is opened
+// with the path as given, whether the name is valid UTF-8 or not.
+fn read_geotags<P: AsRef<Path>>(
+    path: P,
+    waypoints: &mut Vec<GpsInfo>,
+    format: Option<Format>,
+) -> Result<()> {
+    let path = path.as_ref();
+    let name = path.to_string_lossy();
+
+    // Stdin is not seekable, read_input() takes it in full.
+    if path == Path::new("-") {
+        waypoints.append(&mut parse_bytes(&read_input(path)?, &name, format)?);
+        return Ok(());
+    }
+
+    parse_stream(&mut File::open(path)?, &name, waypoints, format).map_err(damaged)
+}
+
+// Reads past the end of the data or values failing to parse, left after a
+// damaged or hostile file, only mean the file is skipped like the ones
+// without geotags. Other errors, like failing to read the file, still stop
+// the run.
+fn damaged(e: Error) -> Error {
+    match e.kind() {
+        ErrorKind::InvalidData | ErrorKind::UnexpectedEof => Skip::Damaged(e.to_string()).into(),
+        _ => e,
+    }
+}
+
+// Whether the GPS data could still be found in a trailer following the image
+// data, where some phones keep a second copy of the Exif data.
+fn trailer_candidate(e: &Error) -> bool {
+    let skip = e.get_ref().and_then(|e| e.downcast_ref::<Skip>());
+    matches!(
+        skip,
+        Some(Skip::NotImage | Skip::NoGps | Skip::Incomplete | Skip::Truncated | Skip::Damaged(_))
+    )
+}
+
+// Look for geotags in the last bytes of a file, trying every TIFF header
+// found there, the one closest to the end first. Exif identifiers and APP1
+// segments are always followed by a TIFF header, so only those headers are
+// searched for.
+fn scan_trailer(tail: &[u8], name: &str, waypoints: &mut Vec<GpsInfo>) -> Result<()> {
+    for pos in (0..tail.len().saturating_sub(3)).rev() {
+        let header = &tail[pos..pos + 4];
+        if header != b"II*\0" && header != b"MM\0*" {
+            continue;
+        }
+
+        let mut buffer = BufReader {
+            cursor_stack: Vec::new(),
+            cursor: 0,
+            buffer: tail[pos..].to_vec(),
+            big_endian: false,
+            big_tiff: false,
+        };
+        if handle_app1(&mut buffer, name, waypoints, None).is_ok() {
+            return Ok(());
+        }
+    }
+    Err(Skip::NoGps.into())
+}
+
+// Read at most `window` bytes at the end of the file.
+fn read_tail(path: &Path, window: u64) -> Result<Vec<u8>> {
+    let mut f = File::open(path)?;
+    let len = f.seek(SeekFrom::End(0))?;
+    let mut tail = Vec::new();
+
+    f.seek(SeekFrom::Start(len.saturating_sub(window)))?;
+    f.read_to_end(&mut tail)?;
+    Ok(tail)
+}
+
+// Files which can not be used are reported and skipped, unless running in
+// strict mode. With `trailer` set files without geotags where expected have
+// that many bytes at their end scanned for the Exif data, see scan_trailer().
+// The files are parsed in parallel, their outcomes are handled in the input
+// order though, so the waypoints and the errors reported are the same as if
+// they were parsed one by one.
+fn parse_files<P: AsRef<Path> + Sync>(
+    files: &[P],
+    strict: bool,
+    format: Option<Format>,
+    trailer: Option<u64>,
+) -> Result<Vec<GpsInfo>> {
+    let results = parallel_map(files, |f| {
+        let path = f.as_ref();
+        let mut found: Vec<GpsInfo> = Vec::new();
+
+        read_geotags(path, &mut found, format)
+            .or_else(|e| match trailer {
+                Some(window) if path != Path::new("-") && trailer_candidate(&e) => {
+                    read_tail(path, window)
+                        .and_then(|tail| scan_trailer(&tail, &path.to_string_lossy(), &mut found))
+                        .map_err(|_| e)
+                }
+                _ => Err(e),
+            })
+            .map(|_| found)
+    });
+    let mut waypoints: Vec<GpsInfo> = Vec::new();
+
+    for (f, result) in files.iter().zip(results) {
+        let f = f.as_ref().display();
+        match result {
+            Err(e) if e.kind() == ErrorKind::Other && !strict => eprintln!("{}: {}", f, e),
+            Err(e) => {
+                eprintln!("{}: {}", f, e);
+                return Err(e);
+            }
+            Ok(mut found) => waypoints.append(&mut found),
+        }
+    }
+    Ok(waypoints)
+}
+
+// Apply `f` to all items using a thread per available CPU, the results are
+// returned in the order of the items.
+fn parallel_map<T, U, F>(items: &[T], f: F) -> Vec<U>
+where
+    T: Sync,
+    U: Send,
+    F: Fn(&T) -> U + Sync,
+{
+    let threads = thread::available_parallelism()
+        .map_or(1, |n| n.get())
+        .min(items.len());
+    let next = AtomicUsize::new(0);
+    let (sender, receiver) = mpsc::channel();
+
+    thread::scope(|s| {
+        for _ in 0..threads {
+            let (sender, next, f) = (sender.clone(), &next, &f);
+            s.spawn(move || loop {
+                let i = next.fetch_add(1, AtomicOrdering::Relaxed);
+                if i >= items.len() {
+                    break;
+                }
+                // The receiver outlives the threads.
+                let _ = sender.send((i, f(&items[i])));
+            });
+        }
+    });
+    drop(sender);
+
+    let mut results: Vec<Option<U>> = items.iter().map(|_| None).collect();
+    for (i, result) in receiver {
+        results[i] = Some(result);
+    }
+    results.into_iter().flatten().collect()
+}
+
+// Retrieve geotags of the JPEG files found in the ZIP archive, other entries
+// are ignored. The waypoints are named after the archive and the entry.
+#[cfg(feature = "zip")]
+fn parse_zip(path: &Path, waypoints: &mut Vec<GpsInfo>, strict: bool) -> Result<()> {
+    let archive = fs::read(path)?;
+
+    for entry in zip::entries(&archive)? {
+        let lower = entry.name.to_lowercase();
+        if !lower.ends_with(".jpg") && !lower.ends_with(".jpeg") {
+            continue;
+        }
+
+        let label = format!("{}/{}", path.display(), entry.name);
+        match zip::read(&archive, &entry).and_then(|bytes| parse_bytes(&bytes, &label, None)) {
+            Ok(mut found) => waypoints.append(&mut found),
+            Err(e) if e.kind() == ErrorKind::Other && !strict => eprintln!("{}: {}", label, e),
+            Err(e) => {
+                eprintln!("{}: {}", label, e);
+                return Err(e);
+            }
+        }
+    }
+    Ok(())
+}
+
+// Outcome of parsing a batch of files, see parse_batch().
+struct BatchResult {
+    waypoints: Vec<GpsInfo>,
+    io_time: Duration,    // Time spent reading the files.
+    parse_time: Duration, // Time spent looking for geotags in the file contents.
+    parsed: usize,        // Files geotags were retrieved from.
+    no_gps: usize,        // Images without geotags.
+    errored: usize,       // Files which could not be read or parsed.
+}
+
+// Read the file, or stdin if the name is "-".
+fn read_input(path: &Path) -> Result<Vec<u8>> {
+    if path == Path::new("-") {
+        let mut bytes = Vec::new();
+
+        io::stdin().read_to_end(&mut bytes)?;
+        return Ok(bytes);
+    }
+    fs::read(path)
+}
+
+// Parse all files, keeping track of the time spent on I/O and on parsing. To
+// keep the two apart each file is read into memory in full before it is
+// parsed. Files which can not be used are reported and counted, they never
+// terminate the batch.
+fn parse_batch(paths: &[PathBuf], format: Option<Format>, trailer: Option<u64>) -> BatchResult {
+    let mut result = BatchResult {
+        waypoints: Vec::new(),
+        io_time: Duration::ZERO,
+        parse_time: Duration::ZERO,
+        parsed: 0,
+        no_gps: 0,
+        errored: 0,
+    };
+
+    for path in paths {
+        let name = path.to_string_lossy();
+        let start = Instant::now();
+        let bytes = read_input(path);
+
+        result.io_time += start.elapsed();
+        let bytes = match bytes {
+            Ok(bytes) => bytes,
+            Err(e) => {
+                eprintln!("{}: {}", name, e);
+                result.errored += 1;
+                continue;
+            }
+        };
+
+        let start = Instant::now();
+        let parsed = parse_bytes(&bytes, &name, format).or_else(|e| match trailer {
+            Some(window) if trailer_candidate(&e) => {
+                let start = bytes
+                    .len()
+                    .saturating_sub(window.min(bytes.len() as u64) as usize);
+                let mut waypoints = Vec::new();
+
+                scan_trailer(&bytes[start..], &name, &mut waypoints).map_err(|_| e)?;
+                Ok(waypoints)
+            }
+            _ => Err(e),
+        });
+
+        result.parse_time += start.elapsed();
+        match parsed {
+            Ok(mut waypoints) => {
+                result.parsed += 1;
+                result.waypoints.append(&mut waypoints);
+            }
+            Err(e) => {
+                eprintln!("{}: {}", name, e);
+                let skip = e.get_ref().and_then(|e| e.downcast_ref::<Skip>());
+                if matches!(skip, Some(Skip::NoGps)) {
+                    result.no_gps += 1;
+                } else {
+                    result.errored += 1;
+                }
+            }
+        }
+    }
+    result
+}
+
+// Human readable representation of an IFD entry value, long arrays are only
+// summarized.
+fn describe_value(buf: &mut BufReader, entry: &IfdEntry) -> Result<String> {
+    const MAX_VALUES: u32 = 16;
+    let (typ_e, count) = (entry.typ_e, entry.count);
+
+    if typ_e == ASCII {
+        return Ok(format!("\"{}\"", string_from_ifd(buf, entry)?));
+    }
+    if count > MAX_VALUES {
+        return Ok(format!("<{} values>", count));
+    }
+
+    let bytes = value_bytes(buf, entry)?;
+    let values: Vec<String> = bytes
+        .chunks(type_size(typ_e))
+        .map(|c| match typ_e {
+            SHORT => buf.u16_from([c[0], c[1]]).to_string(),
+            LONG => buf.u32_from([c[0], c[1], c[2], c[3]]).to_string(),
+            RATIONAL => format!(
+                "{}/{}",
+                buf.u32_from([c[0], c[1], c[2], c[3]]),
+                buf.u32_from([c[4], c[5], c[6], c[7]])
+            ),
+            SRATIONAL => format!(
+                "{}/{}",
+                buf.u32_from([c[0], c[1], c[2], c[3]]) as i32,
+                buf.u32_from([c[4], c[5], c[6], c[7]]) as i32
+            ),
+            _ => c.iter().map(|b| format!("{:02x}", b)).collect(),
+        })
+        .collect();
+    Ok(values.join(" "))
+}
+
+// Print all entries of the IFD at the current cursor position, then descend
+// into the Exif and GPS sub-IFDs it points at.
+fn dump_ifd(buf: &mut BufReader, title: &str, depth: usize) -> Result<()> {
+    let num_entries = read_entry_count(buf)?;
+    let mut sub_ifds: Vec<(&str, u32)> = Vec::new();
+
+    println!("{} ({} entries)", title, num_entries);
+    for _ in 0..num_entries {
+        let entry = read_entry(buf)?;
+        let value = describe_value(buf, &entry).unwrap_or_else(|e| format!("<{}>", e));
+
+        println!("  {}: {}", entry, value);
+        match entry.tag {
+            EXIF_IFD => sub_ifds.push(("Exif IFD", entry.offset)),
+            GPS => sub_ifds.push(("GPS IFD", entry.offset)),
+            _ => {}
+        }
+    }
+
+    // Sub-IFDs are not expected to be nested deeper than Exif IFD -> GPS IFD,
+    // do not let a malformed file send us in circles.
+    if depth < 2 {
+        for (sub_title, offset) in sub_ifds {
+            buf.set_cursor(offset as usize)?;
+            dump_ifd(buf, sub_title, depth + 1)?;
+        }
+    }
+    Ok(())
+}
+
+// Photos and the map of test_parse_file(), built in so that the parser can be
+// checked where the sources are not available.
+const SELF_TEST_FILES: [(&str, &[u8]); 4] = [
+    ("test0.jpg", include_bytes!("test_data/test0.jpg")),
+    ("test1.jpg", include_bytes!("test_data/test1.jpg")),
+    ("test2.jpg", include_bytes!("test_data/test2.jpg")),
+    ("test3.jpg", include_bytes!("test_data/test3.jpg")),
+];
+const SELF_TEST_MAP: &str = include_str!("test_data/result.txt");
+
+// Run the built in photos through the parser and compare the generated map
+// with the expected one, reporting the outcome of every step.
+fn self_test() -> bool {
+    let mut waypoints: Vec<GpsInfo> = Vec::new();
+    let mut passed = true;
+
+    for (name, bytes) in SELF_TEST_FILES.iter() {
+        match parse_bytes(bytes, name, None) {
+            Ok(mut w) => {
+                println!("{}: ok", name);
+                waypoints.append(&mut w);
+            }
+            Err(e) => {
+                println!("{}: FAILED, {}", name, e);
+                passed = false;
+            }
+        }
+    }
+
+    let mut buf: AV = AV::new();
+    let mut opts = OutputOpts::new("Test map");
+    opts.quiet = true;
+    let same = print_xml(&mut waypoints, &mut buf, &opts).is_ok()
+        && buf.as_slice() == SELF_TEST_MAP.as_bytes();
+    if same {
+        println!("result.txt: ok");
+    } else {
+        println!("result.txt: FAILED, the generated map differs");
+        passed = false;
+    }
+
+    println!("Self-test {}", if passed { "passed" } else { "failed" });
+    passed
+}
+
+fn dump_file(path: &Path, format: Option<Format>) -> Result<()> {
+    let mut buffer = read_exif(path, format)?;
+
+    println!("{}:", path.display());
+    let eb = read_header(&mut buffer)?;
+    println!("{}", eb);
+    if !eb.is_valid() {
+        return Err(Skip::BadHeader.into());
+    }
+    dump_ifd(&mut buffer, "IFD0", 0)
+}
+
+// GPS date and time were combined into the number of seconds since Jan 1
+// 1970, write them back as the time element.
+fn print_time(point: &GpsInfo, av: &mut dyn Write, opts: &OutputOpts) -> Result<()> {
+    match output_time(point, opts) {
+        Some(time) => write!(av, "<time>{}</time>", time),
+        None => Ok(()),
+    }
+}
+
+// Time of the waypoint as it goes into the output, UTC unless local time was
+// requested.
+fn output_time(point: &GpsInfo, opts: &OutputOpts) -> Option<String> {
+    let time = point.time?;
+
+    Some(match opts.offset_for {
+        Some(offset_for) => format_local_time(time, offset_for(point.lat, point.longt)),
+        None => format_time(time),
+    })
+}
+
+// Nautical time zone of the location: UTC offset in whole hours, in seconds,
+// as given by the longitude. Good enough at sea and roughly right on land,
+// where the actual time zone borders follow the political ones.
+fn nautical_offset(_lat: f64, longt: f64) -> i32 {
+    (longt / 15.0).round() as i32 * 3600
+}
+
+// Local time in the form of YYYY-MM-DDTHH:MM:SS+HH:MM, `offset` is in seconds
+// east of UTC.
+fn format_local_time(time: u64, offset: i32) -> String {
+    let local = time as i64 + i64::from(offset);
+    let (year, month, day) = civil_from_days(local.div_euclid(86_400));
+    let secs = local.rem_euclid(86_400);
+    let sign = if offset < 0 { '-' } else { '+' };
+    let minutes = offset.unsigned_abs() / 60;
+
+    format!(
+        "{}-{:02}-{:02}T{:02}:{:02}:{:02}{}{:02}:{:02}",
+        year,
+        month,
+        day,
+        secs / 3600,
+        secs / 60 % 60,
+        secs % 60,
+        sign,
+        minutes / 60,
+        minutes % 60
+    )
+}
+
+// Time in the form of YYYY-MM-DDTHH:MM:SSZ.
+fn format_time(time: u64) -> String {
+    let (year, month, day) = civil_from_days((time / 86_400) as i64);
+    let secs = time % 86_400;
+
+    format!(
+        "{}-{:02}-{:02}T{:02}:{:02}:{:02}Z",
+        year,
+        month,
+        day,
+        secs / 3600,
+        secs / 60 % 60,
+        secs % 60
+    )
+}
+
+// GPX 1.0 has no extensions, course is a trackpoint element there and links
+// are expressed using url and urlname elements.
+#[derive(Clone, Copy, PartialEq)]
+enum GpxVersion {
+    V1_0,
+    V1_1,
+}
+
+// Layout of the generated GPX: an element or a trackpoint per line by default,
+// indented elements for hand editing, or everything on a single line.
+#[derive(Clone, Copy, PartialEq)]
+enum Layout {
+    Lines,
+    Pretty,
+    Compact,
+}
+
+// Waypoint representing its source file with --one-per-file, in the order of
+// the waypoint timestamps.
+#[derive(Clone, Copy, PartialEq)]
+enum PerFile {
+    First,
+    Last,
+    Median,
+}
+
+// Elements of the --template string.
+enum Token {
+    Text(String),
+    Lat,
+    Lon,
+    LatDms,
+    LonDms,
+    Alt,
+    Time,
+    File,
+    Epoch,
+}
+
+// Split the template into literal text and {name} tokens, "{{" and "}}"
+// stand for literal braces.
+fn parse_template(template: &str) -> Result<Vec<Token>> {
+    let mut tokens: Vec<Token> = Vec::new();
+    let mut text = String::new();
+    let mut chars = template.chars().peekable();
+
+    while let Some(c) = chars.next() {
+        if (c == '{' || c == '}') && chars.peek() == Some(&c) {
+            chars.next();
+            text.push(c);
+            continue;
+        }
+        if c != '{' {
+            text.push(c);
+            continue;
+        }
+
+        let mut name = String::new();
+        let mut closed = false;
+        for c in chars.by_ref() {
+            if c == '}' {
+                closed = true;
+                break;
+            }
+            name.push(c);
+        }
+        if !closed {
+            eprintln!("Error: unterminated template token {{{}", name);
+            return Err(Error::from(ErrorKind::InvalidData));
+        }
+        let token = match name.as_str() {
+            "lat" => Token::Lat,
+            "lon" => Token::Lon,
+            "lat_dms" => Token::LatDms,
+            "lon_dms" => Token::LonDms,
+            "alt" => Token::Alt,
+            "time" => Token::Time,
+            "file" => Token::File,
+            "epoch" => Token::Epoch,
+            _ => {
+                eprintln!("Error: unknown template token {{{}}}", name);
+                return Err(Error::from(ErrorKind::InvalidData));
+            }
+        };
+        if !text.is_empty() {
+            tokens.push(Token::Text(text.split_off(0)));
+        }
+        tokens.push(token);
+    }
+    if !text.is_empty() {
+        tokens.push(Token::Text(text));
+    }
+    Ok(tokens)
+}
+
+// Print a line per waypoint formatted according to the template. Tokens
+// without a value for the waypoint, like the altitude of a photo recording
+// none, are replaced with nothing.
+fn print_template<'a, I>(
+    track: I,
+    tokens: &[Token],
+    av: &mut dyn Write,
+    opts: &OutputOpts,
+) -> Result<()>
+where
+    I: Iterator<Item = &'a GpsInfo>,
+{
+    for w in track {
+        for token in tokens {
+            match token {
+                Token::Text(text) => write!(av, "{}", text)?,
+                Token::Lat => write!(av, "{:.*}", opts.precision, w.lat)?,
+                Token::Lon => write!(av, "{:.*}", opts.precision, w.longt)?,
+                Token::LatDms => write!(av, "{}", format_dms(w.lat_dms()))?,
+                Token::LonDms => write!(av, "{}", format_dms(w.lon_dms()))?,
+                Token::Alt => {
+                    if let Some(alt) = w.alt {
+                        write!(av, "{:.1}", alt)?;
+                    }
+                }
+                Token::Time => {
+                    if let Some(time) = output_time(w, opts) {
+                        write!(av, "{}", time)?;
+                    }
+                }
+                Token::File => write!(av, "{}", w.file_name)?,
+                Token::Epoch => {
+                    if let Some(time) = w.time {
+                        write!(av, "{}", time)?;
+                    }
+                }
+            }
+        }
+        writeln!(av)?;
+    }
+    Ok(())
+}
+
+// How waypoints are grouped into GPX tracks.
+#[derive(Clone, Copy, PartialEq)]
+enum TrackPer {
+    All,  // A single track named after the map.
+    File, // A track per input file.
+    Dir,  // A track per directory of the input files.
+}
+
+// Parameters controlling generation of the output.
+pub struct OutputOpts {
+    map_name: String,
+    flatten: bool,          // Keep waypoints as parsed, no sorting or deduplication.
+    precision: usize,       // Number of decimal places in coordinates.
+    obfuscate: Option<f64>, // Grid size in meters to snap waypoints to.
+    gpx_version: GpxVersion,
+    template: Option<Vec<Token>>, // Text output instead of GPX.
+    no_clobber: bool,             // Refuse to overwrite existing output files.
+    quiet: bool,                  // Suppress informational messages.
+    track_per: TrackPer,
+    show_dropped: bool, // Emit only the waypoints dropped as duplicates or by -d.
+    ascii_only: bool,   // Keep the output 7-bit clean.
+    layout: Layout,
+    coalesce_time: bool, // Merge waypoints sharing the timestamp instead of dropping.
+    offset_for: Option<fn(f64, f64) -> i32>, // Local time offset for the coordinates.
+    merge_tolerance: Option<f64>, // Meters, merged points this close are duplicates.
+    smooth: Option<usize>, // Number of waypoints averaged, see smooth().
+    one_per_file: Option<PerFile>, // Collapse the waypoints of each file.
+    max_points: Option<usize>, // Trackpoints per track, see downsample().
+    format: &'static dyn OutputFormat,
+    simplify: Option<f64>,     // Meters the simplified tracks can be off by.
+    min_distance: f64,         // Meters between consecutive waypoints, see thin_out().
+    gap_seconds: Option<u64>,  // Time gap starting a new track segment.
+    kml_color: Option<String>, // aabbggrr color of the KML line strings.
+    kml_width: Option<f64>,    // Width of the KML line strings in pixels.
+}
+
+impl OutputOpts {
+    pub fn new(map_name: &str) -> Self {
+        Self {
+            map_name: map_name.to_string(),
+            flatten: false,
+            precision: 5,
+            obfuscate: None,
+            gpx_version: GpxVersion::V1_1,
+            template: None,
+            no_clobber: false,
+            quiet: false,
+            track_per: TrackPer::All,
+            show_dropped: false,
+            ascii_only: false,
+            layout: Layout::Lines,
+            coalesce_time: false,
+            offset_for: None,
+            merge_tolerance: None,
+            smooth: None,
+            min_distance: 5.0,
+            gap_seconds: None,
+            one_per_file: None,
+            max_points: None,
+            format: FORMATS[0],
+            simplify: None,
+            kml_color: None,
+            kml_width: None,
+        }
+    }
+
+    // What the formats registered by the library users can go by.
+    pub fn map_name(&self) -> &str {
+        &self.map_name
+    }
+
+    // Digits after the decimal point of the coordinates.
+    pub fn precision(&self) -> usize {
+        self.precision
+    }
+}
+
+fn print_trackpoint(point: &GpsInfo, av: &mut dyn Write, opts: &OutputOpts) -> Result<()> {
+    write!(av, "<trkpt ")?;
+    write!(
+        av,
+        "lat=\"{:.*}\" lon=\"{:.*}\"> ",
+        opts.precision, point.lat, opts.precision, point.longt
+    )?;
+    if let Some(alt) = point.alt {
+        write!(av, "<ele>{:.1}</ele>", alt)?;
+    }
+    print_time(point, av, opts)?;
+    if opts.gpx_version == GpxVersion::V1_0 {
+        return print_trackpoint_1_0(point, av);
+    }
+    if let Some(area) = &point.area {
+        write!(av, "<desc>{}</desc>", xml_escape(area))?;
+    }
+    if let Some((href, text)) = &point.link {
+        write!(
+            av,
+            "<link href=\"{}\"><text>{}</text></link>",
+            xml_escape(href),
+            xml_escape(text)
+        )?;
+    }
+    if point.has_extensions() {
+        write!(av, "<extensions>")?;
+        if point.speed.is_some() || point.course().is_some() {
+            write!(av, "<gpxtpx:TrackPointExtension>")?;
+            if let Some(speed) = point.speed {
+                write!(av, "<gpxtpx:speed>{:.2}</gpxtpx:speed>", speed)?;
+            }
+            if let Some(course) = point.course() {
+                write!(av, "<gpxtpx:course>{:.2}</gpxtpx:course>", course)?;
+            }
+            write!(av, "</gpxtpx:TrackPointExtension>")?;
+        }
+        if let Some(differential) = point.differential {
+            write!(
+                av,
+                "<exifgeo:differential>{}</exifgeo:differential>",
+                differential as u8
+            )?;
+        }
+        write!(av, "</extensions>")?;
+    }
+    writeln!(av, "</trkpt>")
+}
+
+// Trackpoint contents following the time in GPX 1.0 format, in the order
+// mandated by the 1.0 schema.
+fn print_trackpoint_1_0(point: &GpsInfo, av: &mut dyn Write) -> Result<()> {
+    if let Some(course) = point.course() {
+        write!(av, "<course>{:.2}</course>", course)?;
+    }
+    if let Some(speed) = point.speed {
+        write!(av, "<speed>{:.2}</speed>", speed)?;
+    }
+    if let Some(area) = &point.area {
+        write!(av, "<desc>{}</desc>", xml_escape(area))?;
+    }
+    if let Some((href, text)) = &point.link {
+        write!(
+            av,
+            "<url>{}</url><urlname>{}</urlname>",
+            xml_escape(href),
+            xml_escape(text)
+        )?;
+    }
+    if point.differential == Some(true) {
+        write!(av, "<fix>dgps</fix>")?;
+    }
+    writeln!(av, "</trkpt>")
+}
+
+// Replace non-ASCII characters of the generated output with XML character
+// references, or with '?' in template output, which is not XML. This is
+// applied to the complete output, all of the non-ASCII text in GPX is element
+// content or attribute values.
+fn to_ascii(text: &str, xml: bool) -> String {
+    let mut ascii = String::with_capacity(text.len());
+
+    for c in text.chars() {
+        if c.is_ascii() {
+            ascii.push(c);
+        } else if xml {
+            ascii.push_str(&format!("&#x{:X};", c as u32));
+        } else {
+            ascii.push('?');
+        }
+    }
+    ascii
+}
+
+// Lay out the GPX generated by this program again. The text is split into
+// tags and the text between them, there are no '<' or '>' characters in
+// attribute values or text, as they are escaped. With `Layout::Pretty` each
+// element goes on its own line indented by its depth, elements holding text
+// only staying on one line.
+fn relayout(xml: &str, layout: Layout) -> String {
+    let mut items: Vec<&str> = Vec::new();
+    let mut rest = xml;
+
+    while let Some(start) = rest.find('<') {
+        items.push(&rest[..start]);
+        let end = rest[start..]
+            .find('>')
+            .map_or(rest.len(), |e| start + e + 1);
+        items.push(&rest[start..end]);
+        rest = &rest[end..];
+    }
+    items.push(rest);
+    items.retain(|i| !i.trim().is_empty());
+
+    let mut out = String::with_capacity(xml.len() * 2);
+    if layout == Layout::Compact {
+        out.extend(items.iter().copied());
+        out.push('\n');
+        return out;
+    }
+
+    let mut depth: usize = 0;
+    let mut i = 0;
+    while i < items.len() {
+        let item = items[i];
+        let is_end = |i: usize| items.get(i).is_some_and(|t| t.starts_with("</"));
+        let is_start = !item.starts_with("</") && !item.starts_with("<?") && item.starts_with('<');
+
+        if item.starts_with("</") {
+            depth = depth.saturating_sub(1);
+        }
+        out.push_str(&"  ".repeat(depth));
+        out.push_str(item);
+        if is_start && is_end(i + 1) {
+            out.push_str(items[i + 1]);
+            i += 1;
+        } else if is_start && !items.get(i + 1).is_some_and(|t| t.starts_with('<')) && is_end(i + 2)
+        {
+            out.push_str(items[i + 1].trim());
+            out.push_str(items[i + 2]);
+            i += 2;
+        } else if is_start {
+            depth += 1;
+        }
+        out.push('\n');
+        i += 1;
+    }
+    out
+}
+
+// The generated output as it is written: GPX laid out as requested, then
+// only ASCII if requested. The bytes are written as is otherwise, there is
+// no need for them to be valid UTF-8.
+fn finish_output<'a>(buf: &'a [u8], opts: &OutputOpts, xml: bool) -> Cow<'a, [u8]> {
+    let relayout_xml = xml && opts.layout != Layout::Lines;
+    if !relayout_xml && !opts.ascii_only {
+        return Cow::Borrowed(buf);
+    }
+
+    let mut text = String::from_utf8_lossy(buf).into_owned();
+    if relayout_xml {
+        text = relayout(&text, opts.layout);
+    }
+    if opts.ascii_only {
+        text = to_ascii(&text, xml);
+    }
+    Cow::Owned(text.into_bytes())
+}
+
+fn xml_escape(text: &str) -> String {
+    let mut escaped = String::with_capacity(text.len());
+
+    for c in text.chars() {
+        match c {
+            '&' => escaped.push_str("&amp;"),
+            '<' => escaped.push_str("&lt;"),
+            '>' => escaped.push_str("&gt;"),
+            '"' => escaped.push_str("&quot;"),
+            '\'' => escaped.push_str("&apos;"),
+            _ => escaped.push(c),
+        }
+    }
+    escaped
+}
+
+// Percent-encode everything but the unreserved URL characters, slashes are
+// kept if `path` is set.
+fn percent_encode(text: &str, path: bool) -> String {
+    let mut encoded = String::with_capacity(text.len());
+
+    for b in text.bytes() {
+        match b {
+            b'A'..=b'Z' | b'a'..=b'z' | b'0'..=b'9' | b'-' | b'.' | b'_' | b'~' => {
+                encoded.push(b as char)
+            }
+            b'/' if path => encoded.push('/'),
+            _ => encoded.push_str(&format!("%{:02X}", b)),
+        }
+    }
+    encoded
+}
+
+// Link to the photo the waypoint came from: the photo's file name appended to
+// `base`, or the absolute file:// URL of the photo if there is no base. There
+// is no link for stdin input.
+fn photo_link(file_name: &str, base: Option<&str>) -> Option<(String, String)> {
+    if file_name == "-" {
+        return None;
+    }
+
+    let path = Path::new(file_name);
+    let text = path.file_name()?.to_string_lossy().to_string();
+    let href = match base {
+        Some(base) if base.ends_with('/') => format!("{}{}", base, percent_encode(&text, false)),
+        Some(base) => format!("{}/{}", base, percent_encode(&text, false)),
+        None => {
+            let absolute = fs::canonicalize(path).ok()?;
+            format!(
+                "file://{}",
+                percent_encode(&absolute.to_string_lossy(), true)
+            )
+        }
+    };
+    Some((href, text))
+}
+
+// Waypoints of the same group go into the same track.
+fn track_group<'a>(w: &'a GpsInfo, opts: &OutputOpts) -> &'a str {
+    match opts.track_per {
+        TrackPer::All => "",
+        TrackPer::File => &w.file_name,
+        TrackPer::Dir => Path::new(&w.file_name)
+            .parent()
+            .and_then(|p| p.to_str())
+            .unwrap_or(""),
+    }
+}
+
+fn track_name(w: &GpsInfo, opts: &OutputOpts) -> String {
+    let group = Path::new(track_group(w, opts));
+    let name = match opts.track_per {
+        TrackPer::All => None,
+        TrackPer::File => group.file_name().map(|n| n.to_string_lossy().to_string()),
+        TrackPer::Dir => match group.file_name() {
+            Some(n) => Some(n.to_string_lossy().to_string()),
+            // Photos in the current directory.
+            None => env::current_dir()
+                .ok()
+                .and_then(|d| d.file_name().map(|n| n.to_string_lossy().to_string())),
+        },
+    };
+    name.unwrap_or_else(|| opts.map_name.clone())
+}
+
+fn print_track_start(name: &str, number: usize, av: &mut dyn Write) -> Result<()> {
+    writeln!(av, "<trk>")?;
+    writeln!(
+        av,
+        "<name>{}</name><number>{}</number>",
+        xml_escape(name),
+        number
+    )?;
+    writeln!(av, "<trkseg>")
+}
+
+fn print_track_end(av: &mut dyn Write) -> Result<()> {
+    writeln!(av, "</trkseg>")?;
+    writeln!(av, "</trk>")
+}
+
+// A new track is started whenever the waypoint group changes, waypoints are
+// expected to be ordered by group. Within the track a new segment is started
+// when the time between consecutive waypoints exceeds `gap_seconds`.
+fn print_tracks<'a, I>(track: I, av: &mut dyn Write, opts: &OutputOpts) -> Result<()>
+where
+    I: Iterator<Item = &'a GpsInfo>,
+{
+    let mut group: Option<&str> = None;
+    let mut number = 0;
+    let mut last_time: Option<u64> = None;
+
+    for w in track {
+        if group != Some(track_group(w, opts)) {
+            if group.is_some() {
+                print_track_end(av)?;
+            }
+            number += 1;
+            print_track_start(&track_name(w, opts), number, av)?;
+            group = Some(track_group(w, opts));
+        } else if let (Some(gap), Some(last), Some(time)) = (opts.gap_seconds, last_time, w.time) {
+            if time.abs_diff(last) > gap {
+                writeln!(av, "</trkseg>")?;
+                writeln!(av, "<trkseg>")?;
+            }
+        }
+        if w.time.is_some() {
+            last_time = w.time;
+        }
+        print_trackpoint(w, av, opts)?;
+    }
+    if group.is_none() {
+        print_track_start(&opts.map_name, 1, av)?;
+    }
+    print_track_end(av)
+}
+
+// Namespaces of the GPX 1.1 trackpoint extensions. Extension elements must not
+// be in the GPX namespace, so once there are any the namespaces are declared
+// along with the GPX one.
+const GPX_1_1_NS: &str = "http://www.topografix.com/GPX/1/1";
+const GPXTPX_NS: &str = "http://www.garmin.com/xmlschemas/TrackPointExtension/v2";
+const EXIFGEO_NS: &str = "https://github.com/vbendeb/exifgeo";
+
+// Trackpoints are written into `av` as the iterator produces them, no copy of
+// the track is made. `extensions` tells whether any of the trackpoints carry
+// extensions.
+fn print_gpx<'a, I>(track: I, av: &mut dyn Write, opts: &OutputOpts, extensions: bool) -> Result<()>
+where
+    I: Iterator<Item = &'a GpsInfo>,
+{
+    writeln!(
+        av,
+        "<?xml version=\"1.0\" encoding=\"UTF-8\" standalone=\"yes\" ?>"
+    )?;
+    match opts.gpx_version {
+        GpxVersion::V1_0 => writeln!(
+            av,
+            "<gpx version=\"1.0\" creator=\"git@github.com:vbendeb/exifgeo.git\" \
+             xmlns=\"http://www.topografix.com/GPX/1/0\">"
+        )?,
+        GpxVersion::V1_1 => {
+            write!(
+                av,
+                "<gpx version=\"1.1\" creator=\"git@github.com:vbendeb/exifgeo.git\""
+            )?;
+            if extensions {
+                write!(
+                    av,
+                    " xmlns=\"{}\" xmlns:gpxtpx=\"{}\" xmlns:exifgeo=\"{}\"",
+                    GPX_1_1_NS, GPXTPX_NS, EXIFGEO_NS
+                )?;
+            }
+            writeln!(av, ">")?
+        }
+    }
+    writeln!(av, "<name>{}</name>", xml_escape(&opts.map_name))?;
+    print_tracks(track, av, opts)?;
+    writeln!(av, "</gpx>")
+}
+
+// Writer of the generated map, the built-in ones are listed in FORMATS and
+// the library users can add theirs with register_format(). The waypoints are
+// handed over sorted and deduplicated, to be written into `av`.
+pub trait OutputFormat: Sync {
+    fn name(&self) -> &'static str; // As given to --format.
+    fn extension(&self) -> &'static str;
+    // Whether the output is XML, to be laid out and escaped as such.
+    fn is_xml(&self) -> bool {
+        true
+    }
+    fn write(&self, av: &mut dyn Write, track: &[GpsInfo], opts: &OutputOpts) -> Result<()>;
+}
+
+struct Gpx;
+
+impl OutputFormat for Gpx {
+    fn name(&self) -> &'static str {
+        "gpx"
+    }
+
+    fn extension(&self) -> &'static str {
+        "gpx"
+    }
+
+    fn write(&self, av: &mut dyn Write, track: &[GpsInfo], opts: &OutputOpts) -> Result<()> {
+        let extensions = track.iter().any(|w| w.has_extensions());
+
+        print_gpx(track.iter(), av, opts, extensions)
+    }
+}
+
+struct Kml;
+
+impl OutputFormat for Kml {
+    fn name(&self) -> &'static str {
+        "kml"
+    }
+
+    fn extension(&self) -> &'static str {
+        "kml"
+    }
+
+    fn write(&self, av: &mut dyn Write, track: &[GpsInfo], opts: &OutputOpts) -> Result<()> {
+        print_kml(track, av, opts)
+    }
+}
+
+// A header row and a row of lat,lon,alt,time per trackpoint, the altitude and
+// the time are left empty when unknown.
+struct Csv;
+
+impl OutputFormat for Csv {
+    fn name(&self) -> &'static str {
+        "csv"
+    }
+
+    fn extension(&self) -> &'static str {
+        "csv"
+    }
+
+    fn is_xml(&self) -> bool {
+        false
+    }
+
+    fn write(&self, av: &mut dyn Write, track: &[GpsInfo], opts: &OutputOpts) -> Result<()> {
+        writeln!(av, "lat,lon,alt,time")?;
+        for w in track {
+            write!(
+                av,
+                "{:.*},{:.*},",
+                opts.precision, w.lat, opts.precision, w.longt
+            )?;
+            if let Some(alt) = w.alt {
+                write!(av, "{:.1}", alt)?;
+            }
+            writeln!(av, ",{}", output_time(w, opts).unwrap_or_default())?;
+        }
+        Ok(())
+    }
+}
+
+// An array of objects with the lat, lon, alt and time keys, an object per
+// line. The altitude and the time are null when unknown.
+struct Json;
+
+impl OutputFormat for Json {
+    fn name(&self) -> &'static str {
+        "json"
+    }
+
+    fn extension(&self) -> &'static str {
+        "json"
+    }
+
+    fn is_xml(&self) -> bool {
+        false
+    }
+
+    fn write(&self, av: &mut dyn Write, track: &[GpsInfo], opts: &OutputOpts) -> Result<()> {
+        write!(av, "[")?;
+        for (i, w) in track.iter().enumerate() {
+            write!(
+                av,
+                "{}\n{{\"lat\": {:.*}, \"lon\": {:.*}, \"alt\": ",
+                if i > 0 { "," } else { "" },
+                opts.precision,
+                w.lat,
+                opts.precision,
+                w.longt
+            )?;
+            match w.alt {
+                Some(alt) => write!(av, "{:.1}", alt)?,
+                None => write!(av, "null")?,
+            }
+            match output_time(w, opts) {
+                Some(time) => write!(av, ", \"time\": \"{}\"}}", time)?,
+                None => write!(av, ", \"time\": null}}")?,
+            }
+        }
+        writeln!(av, "\n]")
+    }
+}
+
+// Output formats by --format name, the first one is the default.
+const FORMATS: [&dyn OutputFormat; 4] = [&Gpx, &Kml, &Csv, &Json];
+
+// Formats added with register_format(), looked up after the built-in ones.
+static REGISTERED: Mutex<Vec<&'static dyn OutputFormat>> = Mutex::new(Vec::new());
+
+// Make the format available as --format <name> to run(), unless the name is
+// already taken.
+pub fn register_format(format: &'static dyn OutputFormat) {
+    REGISTERED.lock().unwrap().push(format);
+}
+
+fn formats() -> Vec<&'static dyn OutputFormat> {
+    let mut formats = FORMATS.to_vec();
+
+    formats.extend(REGISTERED.lock().unwrap().iter());
+    formats
+}
+
+fn find_format(name: &str) -> Option<&'static dyn OutputFormat> {
+    formats().into_iter().find(|f| f.name() == name)
+}
+
+// Convert an RRGGBB color to the opaque aabbggrr one KML expects.
+fn kml_color(rgb: &str) -> Option<String> {
+    if rgb.len() != 6 || !rgb.bytes().all(|b| b.is_ascii_hexdigit()) {
+        return None;
+    }
+    Some(format!("ff{}{}{}", &rgb[4..], &rgb[2..4], &rgb[..2]).to_ascii_lowercase())
+}
+
+// KML document with a placemark per track: a line string through the
+// trackpoints, or a point when there is just one of them. Unlike GPX the
+// coordinates go longitude first. With a line color or width all placemarks
+// refer to a single style, otherwise the viewer picks the look.
+fn print_kml(track: &[GpsInfo], av: &mut dyn Write, opts: &OutputOpts) -> Result<()> {
+    let styled = opts.kml_color.is_some() || opts.kml_width.is_some();
+
+    writeln!(av, "<?xml version=\"1.0\" encoding=\"UTF-8\"?>")?;
+    writeln!(av, "<kml xmlns=\"http://www.opengis.net/kml/2.2\">")?;
+    writeln!(av, "<Document>")?;
+    writeln!(av, "<name>{}</name>", xml_escape(&opts.map_name))?;
+    if styled {
+        write!(av, "<Style id=\"track\"><LineStyle>")?;
+        if let Some(color) = &opts.kml_color {
+            write!(av, "<color>{}</color>", color)?;
+        }
+        if let Some(width) = opts.kml_width {
+            write!(av, "<width>{}</width>", width)?;
+        }
+        writeln!(av, "</LineStyle></Style>")?;
+    }
+
+    let mut start = 0;
+    while start < track.len() {
+        let group = track_group(&track[start], opts);
+        let len = track[start..]
+            .iter()
+            .take_while(|w| track_group(w, opts) == group)
+            .count();
+        let points = &track[start..start + len];
+        let geometry = if len == 1 { "Point" } else { "LineString" };
+
+        writeln!(av, "<Placemark>")?;
+        writeln!(
+            av,
+            "<name>{}</name>",
+            xml_escape(&track_name(&points[0], opts))
+        )?;
+        if styled {
+            writeln!(av, "<styleUrl>#track</styleUrl>")?;
+        }
+        writeln!(av, "<{}>", geometry)?;
+        write!(av, "<coordinates>")?;
+        for (i, w) in points.iter().enumerate() {
+            if i > 0 {
+                write!(av, " ")?;
+            }
+            write!(
+                av,
+                "{:.*},{:.*}",
+                opts.precision, w.longt, opts.precision, w.lat
+            )?;
+            if let Some(alt) = w.alt {
+                write!(av, ",{:.1}", alt)?;
+            }
+        }
+        writeln!(av, "</coordinates>")?;
+        writeln!(av, "</{}>", geometry)?;
+        writeln!(av, "</Placemark>")?;
+        start += len;
+    }
+    writeln!(av, "</Document>")?;
+    writeln!(av, "</kml>")
+}
+
+// Waypoints without time go after the rest, ordered by file name. Waypoints
+// sharing the time, like burst shots, are ordered by latitude, longitude and
+// file name, so the waypoints surviving the deduplication do not depend on
+// the order of the input files. Only the waypoints identical in all of that
+// are left in the input order.
+fn chronological(a: &GpsInfo, b: &GpsInfo) -> Ordering {
+    match (a.time, b.time) {
+        (Some(ta), Some(tb)) => ta.cmp(&tb),
+        (Some(_), None) => Ordering::Less,
+        (None, Some(_)) => Ordering::Greater,
+        (None, None) => a.file_name.cmp(&b.file_name),
+    }
+    .then(a.lat.total_cmp(&b.lat))
+    .then(a.longt.total_cmp(&b.longt))
+    .then_with(|| a.file_name.cmp(&b.file_name))
+    .then(a.seq.cmp(&b.seq))
+}
+
+// Unless `flatten` is requested the waypoints are sorted by track and time,
+// those within `min_distance` of the previous one are thinned out first, then
+// those sharing the track and timestamp with the previous one are dropped.
+// When flattening all waypoints are emitted in the order they were parsed.
+// With `show_dropped` only the dropped waypoints are emitted, with
+// `coalesce_time` waypoints sharing the timestamp are merged rather than
+// dropped. Obfuscation, if requested, is applied after averaging the
+// positions, so that none of them ends up off the grid. Tracks are downsampled
+// last, once the positions are final. The output is GPX unless a template is
+// given.
+fn print_xml(waypoints: &mut Vec<GpsInfo>, av: &mut AV, opts: &OutputOpts) -> Result<()> {
+    let mut summary = Summary::default();
+
+    if let Some(pick) = opts.one_per_file {
+        one_per_file(waypoints, pick);
+    }
+    if !opts.flatten {
+        waypoints.sort_by(chronological);
+        if opts.track_per != TrackPer::All {
+            // Stable, keeps the waypoints within the group chronological.
+            waypoints.sort_by(|a, b| track_group(a, opts).cmp(track_group(b, opts)));
+        }
+        if opts.coalesce_time {
+            coalesce(waypoints, opts);
+        }
+    }
+    if let Some(window) = opts.smooth {
+        smooth(waypoints, window, opts);
+    }
+    if let Some(meters) = opts.obfuscate {
+        for w in waypoints.iter_mut() {
+            w.snap_to_grid(meters);
+        }
+    }
+    // Flattened waypoints go out exactly as parsed, no thinning either.
+    let mut keep: Vec<bool> = vec![true; waypoints.len()];
+    if opts.min_distance > 0.0 && !opts.flatten {
+        keep = thin_out(waypoints, opts);
+        let dropped = keep.iter().filter(|&&k| !k).count();
+        if dropped > 0 && !opts.quiet {
+            eprintln!(
+                "Dropped {} waypoint(s) within {} m of the previous one",
+                dropped, opts.min_distance
+            );
+        }
+    }
+    if opts.show_dropped {
+        // The complement: the waypoints thinned out and the duplicates.
+        for (i, k) in keep.iter_mut().enumerate() {
+            *k = !*k || is_duplicate(waypoints, i, opts);
+        }
+        retain_marked(waypoints, keep);
+    } else {
+        retain_marked(waypoints, keep);
+        if (opts.max_points.is_some() || opts.simplify.is_some()) && !opts.flatten {
+            downsample(waypoints, opts);
+        }
+        deduplicate(waypoints, opts);
+    }
+
+    match &opts.template {
+        Some(tokens) => print_template(waypoints.iter(), tokens, av, opts)?,
+        None => opts.format.write(av, waypoints, opts)?,
+    }
+    if !opts.quiet {
+        for w in waypoints.iter() {
+            summary.add(w);
+        }
+        summary.print();
+    }
+    Ok(())
+}
+
+// Drop the duplicates of the sorted waypoints, in place so that the output is
+// written straight from them.
+fn deduplicate(waypoints: &mut Vec<GpsInfo>, opts: &OutputOpts) {
+    let keep: Vec<bool> = (0..waypoints.len())
+        .map(|i| !is_duplicate(waypoints, i, opts))
+        .collect();
+
+    retain_marked(waypoints, keep);
+}
+
+// Whether the waypoint shares the track and timestamp with the previous one.
+fn is_duplicate(waypoints: &[GpsInfo], i: usize, opts: &OutputOpts) -> bool {
+    let w = &waypoints[i];
+
+    !opts.flatten
+        && i > 0
+        && w.time.is_some()
+        && w.time == waypoints[i - 1].time
+        && track_group(w, opts) == track_group(&waypoints[i - 1], opts)
+}
+
+// Drop the duplicates and simplify every track down to at most `max_points`
+// trackpoints, or as far as it stays within `simplify` meters of the
+// original one, see simplify().
+fn downsample(waypoints: &mut Vec<GpsInfo>, opts: &OutputOpts) {
+    let max_points = opts.max_points.unwrap_or(usize::MAX);
+    deduplicate(waypoints, opts);
+    let before = waypoints.len();
+    let mut keep: Vec<bool> = Vec::with_capacity(before);
+    let mut start = 0;
+
+    while start < waypoints.len() {
+        let group = track_group(&waypoints[start], opts);
+        let len = waypoints[start..]
+            .iter()
+            .take_while(|w| track_group(w, opts) == group)
+            .count();
+        let track = &waypoints[start..start + len];
+        keep.extend(simplify(track, max_points, opts.simplify));
+        start += len;
+    }
+    retain_marked(waypoints, keep);
+    if waypoints.len() != before && !opts.quiet {
+        eprintln!(
+            "Dropped {} waypoint(s) simplifying the track(s)",
+            before - waypoints.len()
+        );
+    }
+}
+
+// Ramer-Douglas-Peucker simplification, ranked: starting with the ends of the
+// track, the point farthest from the simplified track is put back until
+// `max_points` of them are kept, or until none of the rest is farther than
+// `epsilon` meters. Returns which of the points are kept.
+fn simplify(points: &[GpsInfo], max_points: usize, epsilon: Option<f64>) -> Vec<bool> {
+    let n = points.len();
+    if n <= 2 || (n <= max_points && epsilon.is_none()) {
+        return vec![true; n];
+    }
+
+    // The point farthest from the segment between the kept points `a` and
+    // `b`, along with its distance.
+    let farthest = |a: usize, b: usize| {
+        (a + 1..b)
+            .map(|i| {
+                (
+                    segment_distance(&points[i], &points[a], &points[b]),
+                    a,
+                    i,
+                    b,
+                )
+            })
+            .max_by(|x, y| x.0.total_cmp(&y.0))
+    };
+    let mut segments: Vec<(f64, usize, usize, usize)> = farthest(0, n - 1).into_iter().collect();
+    let mut keep = vec![false; n];
+
+    keep[0] = true;
+    keep[n - 1] = true;
+    for _ in 2..max_points.min(n) {
+        let next = (0..segments.len()).max_by(|&x, &y| segments[x].0.total_cmp(&segments[y].0));
+        let (_, a, i, b) = match next {
+            Some(next) if epsilon.is_none_or(|e| segments[next].0 > e) => {
+                segments.swap_remove(next)
+            }
+            _ => break,
+        };
+        keep[i] = true;
+        segments.extend(farthest(a, i));
+        segments.extend(farthest(i, b));
+    }
+    keep
+}
+
+// Distance in meters from the waypoint to the closest point of the segment
+// between `a` and `b`, the closest point is found in the plane tangent at `a`.
+fn segment_distance(w: &GpsInfo, a: &GpsInfo, b: &GpsInfo) -> f64 {
+    let scale = a.lat.to_radians().cos();
+    let (bx, by) = (wrap_longitude(b.longt - a.longt), b.lat - a.lat);
+    let (wx, wy) = (wrap_longitude(w.longt - a.longt), w.lat - a.lat);
+    let len = (bx * scale).powi(2) + by.powi(2);
+    let t = if len > 0.0 {
+        ((wx * bx * scale * scale + wy * by) / len).clamp(0.0, 1.0)
+    } else {
+        0.0
+    };
+    let closest = GpsInfo {
+        lat: a.lat + t * by,
+        longt: wrap_longitude(a.longt + t * bx),
+        ..GpsInfo::new()
+    };
+
+    w.distance_from(&closest)
+}
+
+// Read the generated GPX back and compare its trackpoints with the waypoints
+// it was generated from. Coordinates are expected to match within the output
+// precision.
+fn verify_output(text: &str, expected: &[GpsInfo], opts: &OutputOpts) -> Result<()> {
+    let mut parsed: Vec<GpsInfo> = Vec::new();
+    let tolerance = 0.5 / 10f64.powi(opts.precision as i32) + 1e-9;
+
+    parse_gpx(text, "output", &mut parsed)?;
+    if parsed.len() != expected.len() {
+        eprintln!(
+            "Error: verification failed, {} trackpoint(s) written, {} read back",
+            expected.len(),
+            parsed.len()
+        );
+        return Err(Error::from(ErrorKind::InvalidData));
+    }
+    for (p, w) in parsed.iter().zip(expected) {
+        if (p.lat - w.lat).abs() > tolerance
+            || (p.longt - w.longt).abs() > tolerance
+            || p.time != w.time
+        {
+            eprintln!(
+                "Error: verification failed, trackpoint of {} read back as {}, {}",
+                w.file_name, p.lat, p.longt
+            );
+            return Err(Error::from(ErrorKind::InvalidData));
+        }
+    }
+    if !opts.quiet {
+        eprintln!("Verified {} trackpoint(s)", parsed.len());
+    }
+    Ok(())
+}
+
+// Normalize longitude into [-180, 180).
+fn wrap_longitude(longt: f64) -> f64 {
+    (longt + 180.0).rem_euclid(360.0) - 180.0
+}
+
+// Replace runs of consecutive waypoints sharing the timestamp and the track
+// with a single waypoint at their mean position, otherwise keeping the first
+// waypoint of the run. Longitudes are averaged as offsets from the running
+// mean, so that runs straddling the antimeridian stay where they are.
+fn coalesce(waypoints: &mut Vec<GpsInfo>, opts: &OutputOpts) {
+    let mut merged: Vec<GpsInfo> = Vec::with_capacity(waypoints.len());
+    let mut n = 0.0;
+
+    for w in waypoints.drain(..) {
+        match merged.last_mut() {
+            Some(last)
+                if w.time.is_some()
+                    && w.time == last.time
+                    && track_group(&w, opts) == track_group(last, opts) =>
+            {
+                n += 1.0;
+                last.lat += (w.lat - last.lat) / n;
+                let offset = wrap_longitude(w.longt - last.longt);
+                last.longt = wrap_longitude(last.longt + offset / n);
+            }
+            _ => {
+                n = 1.0;
+                merged.push(w);
+            }
+        }
+    }
+    *waypoints = merged;
+}
+
+// Which waypoints to keep so that none lies within `min_distance` meters of
+// the previous waypoint kept in the same track, like the photos taken while
+// standing in one place. Each waypoint is compared with the last one kept
+// rather than with its immediate predecessor, which might have been dropped
+// itself: a slow walk is not lost step by step, and a waypoint following a
+// dropped near-duplicate is measured against the position actually on the
+// track.
+fn thin_out(waypoints: &[GpsInfo], opts: &OutputOpts) -> Vec<bool> {
+    let mut keep: Vec<bool> = Vec::with_capacity(waypoints.len());
+    let mut last: Option<&GpsInfo> = None;
+
+    for w in waypoints {
+        let close = last.is_some_and(|last| {
+            track_group(w, opts) == track_group(last, opts)
+                && w.distance_from_fast(last) <= opts.min_distance
+        });
+        if !close {
+            last = Some(w);
+        }
+        keep.push(!close);
+    }
+    keep
+}
+
+// Drop the waypoints not marked in `keep`, which holds a flag per waypoint.
+fn retain_marked(waypoints: &mut Vec<GpsInfo>, keep: Vec<bool>) {
+    let mut keep = keep.into_iter();
+
+    waypoints.retain(|_| keep.next().unwrap_or(true));
+}
+
+// Keep a single waypoint of each source file, the files stay in the order
+// they were first seen in.
+fn one_per_file(waypoints: &mut Vec<GpsInfo>, pick: PerFile) {
+    let mut files: Vec<Vec<GpsInfo>> = Vec::new();
+    let mut index: HashMap<String, usize> = HashMap::new();
+
+    for w in waypoints.drain(..) {
+        let i = *index.entry(w.file_name.clone()).or_insert_with(|| {
+            files.push(Vec::new());
+            files.len() - 1
+        });
+        files[i].push(w);
+    }
+    *waypoints = files
+        .into_iter()
+        .map(|mut file| {
+            file.sort_by(chronological);
+            let i = match pick {
+                PerFile::First => 0,
+                PerFile::Last => file.len() - 1,
+                PerFile::Median => (file.len() - 1) / 2,
+            };
+            file.swap_remove(i)
+        })
+        .collect();
+}
+
+// Move each waypoint to the weighted mean position of the `window` waypoints
+// centered on it, ignoring the ones of other tracks. The weights are the
+// inverse squares of the estimated errors, precise fixes pull the others
+// towards them while barely moving themselves. Waypoints of unknown quality
+// are assumed to have the typical error.
+fn smooth(waypoints: &mut [GpsInfo], window: usize, opts: &OutputOpts) {
+    let half = window / 2;
+    let smoothed: Vec<(f64, f64)> = (0..waypoints.len())
+        .map(|i| {
+            let center = &waypoints[i];
+            let end = (i + half + 1).min(waypoints.len());
+            let (mut lat, mut offset, mut total) = (0.0, 0.0, 0.0);
+
+            for w in &waypoints[i.saturating_sub(half)..end] {
+                if track_group(w, opts) != track_group(center, opts) {
+                    continue;
+                }
+                let weight = w.quality().unwrap_or(UERE).max(0.1).powi(-2);
+                lat += w.lat * weight;
+                offset += wrap_longitude(w.longt - center.longt) * weight;
+                total += weight;
+            }
+            (lat / total, wrap_longitude(center.longt + offset / total))
+        })
+        .collect();
+
+    for (w, (lat, longt)) in waypoints.iter_mut().zip(smoothed) {
+        w.lat = lat;
+        w.longt = longt;
+    }
+}
+
+// Extension of the output file for the generated output, none for the
+// template output, which can be anything.
+fn output_extension(opts: &OutputOpts) -> Option<&'static str> {
+    match opts.template {
+        Some(_) => None,
+        None => Some(opts.format.extension()),
+    }
+}
+
+// Make sure the output file name has the extension: append it if `fix` is
+// set, otherwise just warn.
+fn check_extension(name: PathBuf, extension: &str, fix: bool) -> PathBuf {
+    let matching = name
+        .extension()
+        .is_some_and(|e| e.eq_ignore_ascii_case(extension));
+
+    if matching {
+        name
+    } else if fix {
+        let mut name = name.into_os_string();
+        name.push(format!(".{}", extension));
+        PathBuf::from(name)
+    } else {
+        eprintln!(
+            "Warning: output file {} does not have the .{} extension",
+            name.display(),
+            extension
+        );
+        name
+    }
+}
+
+// Write the output file atomically: the data goes into a temporary file in
+// the same directory, which then replaces the output file, so an interrupted
+// run never leaves a truncated file behind. fs::rename() replaces existing
+// files on Windows too. With `no_clobber` the temporary file is hard linked
+// instead, which fails if the output file exists.
+fn write_output(path: &Path, bytes: &[u8], opts: &OutputOpts) -> Result<()> {
+    let mut temp = path.as_os_str().to_owned();
+    temp.push(format!(".{}.tmp", std::process::id()));
+    let temp = PathBuf::from(temp);
+
+    let mut f = OpenOptions::new()
+        .write(true)
+        .create_new(true)
+        .open(&temp)?;
+    let mut result = f.write_all(bytes).and_then(|_| f.sync_all());
+
+    drop(f); // Open files can not be renamed on Windows.
+    if result.is_ok() {
+        result = if opts.no_clobber {
+            fs::hard_link(&temp, path)
+        } else {
+            fs::rename(&temp, path)
+        };
+    }
+    if result.is_err() || opts.no_clobber {
+        fs::remove_file(&temp)?;
+    }
+    match result {
+        Err(e) if e.kind() == ErrorKind::AlreadyExists => {
+            eprintln!("Error: {} already exists", path.display());
+            Err(e)
+        }
+        r => r,
+    }
+}
+
+// Write each waypoint into its own map file in the `dir` directory, named
+// after the photo the waypoint came from. Photos with the same name found in
+// different directories get a numeric suffix added.
+fn split_output(waypoints: &[GpsInfo], dir: &Path, opts: &OutputOpts) -> Result<()> {
+    let mut used: HashSet<String> = HashSet::new();
+
+    fs::create_dir_all(dir)?;
+    for w in waypoints {
+        let stem = match Path::new(&w.file_name).file_stem() {
+            Some(s) => s.to_string_lossy().to_string(),
+            None => "stdin".to_string(),
+        };
+        let mut name = stem.clone();
+        let mut n = 1;
+
+        while !used.insert(name.clone()) {
+            n += 1;
+            name = format!("{}-{}", stem, n);
+        }
+
+        let mut buf = AV::new();
+        opts.format.write(&mut buf, std::slice::from_ref(w), opts)?;
+        let path = dir.join(name + "." + opts.format.extension());
+        write_output(
+            &path,
+            &finish_output(&buf, opts, opts.format.is_xml()),
+            opts,
+        )?;
+    }
+    if !opts.quiet {
+        eprintln!("Wrote {} file(s) into {}", waypoints.len(), dir.display());
+    }
+    Ok(())
+}
+
+// What ended up in the track: number of points, distance covered going from
+// point to point, elevation gain and the time between the earliest and the
+// latest point.
+#[derive(Default)]
+struct Summary<'a> {
+    points: usize,
+    distance: f64,
+    first: Option<u64>,
+    last: Option<u64>,
+    previous: Option<&'a GpsInfo>,
+    // Sum of the climbs between consecutive points with altitude, points
+    // without it are skipped.
+    gain: f64,
+    altitude: Option<f64>,
+}
+
+impl<'a> Summary<'a> {
+    fn add(&mut self, w: &'a GpsInfo) {
+        self.points += 1;
+        if let Some(previous) = self.previous {
+            self.distance += w.distance_from(previous);
+        }
+        if let Some(time) = w.time {
+            self.first = Some(self.first.map_or(time, |t| t.min(time)));
+            self.last = Some(self.last.map_or(time, |t| t.max(time)));
+        }
+        if let Some(alt) = w.alt {
+            if let Some(previous) = self.altitude {
+                self.gain += (alt - previous).max(0.0);
+            }
+            self.altitude = Some(alt);
+        }
+        self.previous = Some(w);
+    }
+
+    // Time span as H:MM:SS.
+    fn span(&self) -> String {
+        match (self.first, self.last) {
+            (Some(first), Some(last)) => {
+                let span = last - first;
+                format!("{}:{:02}:{:02}", span / 3600, span / 60 % 60, span % 60)
+            }
+            _ => "unknown".to_string(),
+        }
+    }
+
+    fn print(&self) {
+        eprintln!(
+            "{} points, {:.3} km, {:.0} m elevation gain, time span {}",
+            self.points,
+            self.distance / 1000.0,
+            self.gain,
+            self.span()
+        );
+    }
+}
+
+// Reverse of xml_escape().
+fn xml_unescape(text: &str) -> String {
+    text.replace("&lt;", "<")
+        .replace("&gt;", ">")
+        .replace("&quot;", "\"")
+        .replace("&apos;", "'")
+        .replace("&amp;", "&")
+}
+
+// Value of the attribute in an XML start tag.
+fn xml_attr<'a>(tag: &'a str, attr: &str) -> Option<&'a str> {
+    let pattern = format!("{}=", attr);
+
+    for (pos, _) in tag.match_indices(&pattern) {
+        if !tag[..pos].ends_with(char::is_whitespace) {
+            continue;
+        }
+        let rest = &tag[pos + pattern.len()..];
+        let quote = rest.chars().next()?;
+        if quote != '"' && quote != '\'' {
+            return None;
+        }
+        return rest[1..].find(quote).map(|end| &rest[1..end + 1]);
+    }
+    None
+}
+
+// Text contents of the first element with the given name in an XML fragment.
+fn xml_element<'a>(xml: &'a str, element: &str) -> Option<&'a str> {
+    let open = format!("<{}>", element);
+    let close = format!("</{}>", element);
+    let start = xml.find(&open)? + open.len();
+    let end = start + xml[start..].find(&close)?;
+
+    Some(xml[start..end].trim())
+}
+
+// Convert GPX time in the form of YYYY-MM-DDTHH:MM:SS followed by Z or the
+// +HH:MM offset into the number of seconds since Jan 1 1970. Fractions of
+// seconds are ignored, time without the offset is presumed to be UTC.
+fn parse_gpx_time(text: &str) -> Result<u64> {
+    let b = text.as_bytes();
+
+    if b.len() < 19 || b[4] != b'-' || b[7] != b'-' || b[10] != b'T' {
+        eprintln!("unsupported time format {}", text);
+        return Err(Error::from(ErrorKind::InvalidData));
+    }
+    let date = date_to_seconds(get_num(&b[0..4])?, get_num(&b[5..7])?, get_num(&b[8..10])?)?;
+    let time =
+        date + get_num(&b[11..13])? * 3600 + get_num(&b[14..16])? * 60 + get_num(&b[17..19])?;
+
+    let zone = text[19..].trim_start_matches(|c: char| c == '.' || c.is_ascii_digit());
+    let zb = zone.as_bytes();
+    if zb.len() != 6 || zb[3] != b':' || (zb[0] != b'+' && zb[0] != b'-') {
+        return Ok(time);
+    }
+    let offset = (get_num(&zb[1..3])? * 3600 + get_num(&zb[4..6])? * 60) as i64;
+    let offset = if zb[0] == b'-' { -offset } else { offset };
+    time.checked_add_signed(-offset).ok_or_else(|| {
+        eprintln!("invalid date {}", text);
+        Error::from(ErrorKind::InvalidData)
+    })
+}
+
+// Retrieve trackpoints from the GPX text, only the subset of GPX generated by
+// this program is supported: coordinates, elevation, time, link, course and
+// differential correction.
+fn parse_gpx(text: &str, name: &str, waypoints: &mut Vec<GpsInfo>) -> Result<()> {
+    let mut rest = text;
+
+    while let Some(start) = rest.find("<trkpt") {
+        rest = &rest[start..];
+        let tag_end = rest.find('>').unwrap_or(rest.len());
+        let tag = &rest[..tag_end];
+        let body_end = if tag.ends_with('/') {
+            tag_end
+        } else {
+            rest.find("</trkpt>").unwrap_or(rest.len())
+        };
+        let body = &rest[tag_end..body_end];
+        let mut waypoint: GpsInfo = GpsInfo::new();
+
+        let lat = xml_attr(tag, "lat").and_then(|v| v.parse().ok());
+        let longt = xml_attr(tag, "lon").and_then(|v| v.parse().ok());
+        match (lat, longt) {
+            (Some(lat), Some(longt)) => {
+                waypoint.lat = lat;
+                waypoint.longt = longt;
+            }
+            _ => {
+                eprintln!("{}: bad trackpoint {}>", name, tag);
+                return Err(Error::from(ErrorKind::InvalidData));
+            }
+        }
+        waypoint.alt = xml_element(body, "ele").and_then(|v| v.parse().ok());
+        if let Some(time) = xml_element(body, "time") {
+            waypoint.time = Some(parse_gpx_time(time)?);
+        }
+        if let Some(start) = body.find("<link ") {
+            let link = &body[start..];
+            let link_tag = &link[..link.find('>').unwrap_or(link.len())];
+
+            if let Some(href) = xml_attr(link_tag, "href") {
+                let text = xml_element(link, "text").unwrap_or("");
+                waypoint.link = Some((xml_unescape(href), xml_unescape(text)));
+            }
+        } else if let Some(url) = xml_element(body, "url") {
+            let text = xml_element(body, "urlname").unwrap_or("");
+            waypoint.link = Some((xml_unescape(url), xml_unescape(text)));
+        }
+        // Extensions are unprefixed in files generated by older versions.
+        waypoint.track = xml_element(body, "gpxtpx:course")
+            .or_else(|| xml_element(body, "course"))
+            .and_then(|v| v.parse().ok());
+        waypoint.speed = xml_element(body, "gpxtpx:speed")
+            .or_else(|| xml_element(body, "speed"))
+            .and_then(|v| v.parse().ok());
+        waypoint.differential = xml_element(body, "exifgeo:differential")
+            .or_else(|| xml_element(body, "differential"))
+            .map(|v| v == "1");
+        if xml_element(body, "fix") == Some("dgps") {
+            waypoint.differential = Some(true);
+        }
+        waypoint.file_name = name.to_string();
+        waypoints.push(waypoint);
+
+        rest = &rest[body_end..];
+    }
+    Ok(())
+}
+
+// With `merge_tolerance` trackpoints of the GPX file lying that close to any of
+// the waypoints found so far are considered duplicates of them and dropped,
+// whatever their timestamps.
+fn read_gpx(path: &Path, waypoints: &mut Vec<GpsInfo>, opts: &OutputOpts) -> Result<()> {
+    let text = fs::read_to_string(path)?;
+    let name = path.to_string_lossy();
+    let mut merged: Vec<GpsInfo> = Vec::new();
+
+    parse_gpx(&text, &name, &mut merged)?;
+    if let Some(limit) = opts.merge_tolerance {
+        filter_waypoints(
+            &mut merged,
+            |m| waypoints.iter().all(|w| w.distance_from_fast(m) > limit),
+            &format!("of {} within {} m of the photos", name, limit),
+            opts,
+        );
+    }
+    if !opts.quiet {
+        eprintln!("Merged {} trackpoint(s) from {}", merged.len(), name);
+    }
+    waypoints.append(&mut merged);
+    Ok(())
+}
+
+fn prepare_opts() -> Options {
+    let mut o = Options::new();
+
+    o.optopt(
+        "m",
+        "map_name",
+        "Name of the generated map, derived from the input by default",
+        "",
+    );
+    o.optopt(
+        "o",
+        "output_file",
+        "Output file name, console by default",
+        "",
+    );
+    o.optflag(
+        "",
+        "flatten",
+        "Keep all waypoints in input order, no sorting or deduplication",
+    );
+    o.optflag(
+        "",
+        "dump",
+        "Print Exif entries of the input files instead of generating a map",
+    );
+    o.optflag(
+        "",
+        "selftest",
+        "Check the parser against the built in test photos and exit",
+    );
+    o.optopt(
+        "",
+        "max-accuracy",
+        "Drop waypoints with horizontal positioning error above this",
+        "METERS",
+    );
+    o.optopt(
+        "",
+        "split-output",
+        "Write a GPX file per input photo into this directory",
+        "DIR",
+    );
+    o.optopt(
+        "",
+        "merge",
+        "Add trackpoints of this GPX file to the generated map",
+        "GPX",
+    );
+    o.optopt(
+        "",
+        "merge-tolerance",
+        "Drop merged trackpoints this close to any of the photos",
+        "METERS",
+    );
+    o.optopt(
+        "",
+        "precision",
+        "Number of decimal places in coordinates, 0 to 9, 5 by default",
+        "N",
+    );
+    o.optopt(
+        "",
+        "obfuscate",
+        "Snap waypoints to a grid of this size for privacy",
+        "METERS",
+    );
+    o.optopt(
+        "",
+        "max-error",
+        "Drop waypoints with the positioning error, reported or estimated from DOP, above this",
+        "METERS",
+    );
+    o.optopt(
+        "",
+        "min-satellites",
+        "Drop waypoints fixed using fewer satellites than this",
+        "N",
+    );
+    o.optopt(
+        "",
+        "polygon",
+        "Keep only waypoints inside the polygon of these vertices",
+        "LAT,LON;LAT,LON;...",
+    );
+    o.optflag("", "links", "Link trackpoints to the source photos");
+    o.optopt(
+        "",
+        "link-base",
+        "Base URL of the photo links, implies --links",
+        "URL",
+    );
+    o.optflag(
+        "",
+        "require-differential",
+        "Keep only waypoints with differential correction applied",
+    );
+    o.optflag(
+        "",
+        "stats",
+        "Report the number of parsed files and the time it took",
+    );
+    o.optflag("", "strict", "Fail on any input file which can not be used");
+    o.optflag(
+        "r",
+        "recursive",
+        "Look for photos in the subdirectories of the directories given too",
+    );
+    o.optflag(
+        "",
+        "follow-symlinks",
+        "Follow symbolic links found in the directories given",
+    );
+    o.optopt(
+        "",
+        "max-files",
+        "Process only this many of the input files, for a quick preview",
+        "N",
+    );
+    o.optopt(
+        "",
+        "scan-trailer",
+        "Look for the Exif data in the last KB kilobytes of photos without geotags where expected",
+        "KB",
+    );
+    o.optopt(
+        "",
+        "force-format",
+        "Read the input files as this format instead of detecting it",
+        &Format::ALL.map(|f| f.name()).join("|"),
+    );
+    o.optopt(
+        "",
+        "format",
+        &format!(
+            "Format of the generated map, EXIFGEO_FORMAT or {} by default",
+            FORMATS[0].name()
+        ),
+        &formats()
+            .iter()
+            .map(|f| f.name())
+            .collect::<Vec<_>>()
+            .join("|"),
+    );
+    o.optopt(
+        "",
+        "kml-color",
+        "Color of the KML tracks, ignored for other formats",
+        "RRGGBB",
+    );
+    o.optopt(
+        "",
+        "kml-width",
+        "Width of the KML tracks, ignored for other formats",
+        "PIXELS",
+    );
+    o.optopt(
+        "",
+        "altitude-source",
+        "Altitude written to the output, gps, the default, is the only one read",
+        "gps|relative|absolute",
+    );
+    o.optopt(
+        "",
+        "gpx-version",
+        "GPX version to generate, 1.1 by default",
+        "1.0|1.1",
+    );
+    o.optopt(
+        "",
+        "template",
+        "Print a line per waypoint instead of GPX, with {lat}, {lon}, {time} etc. substituted",
+        "TEXT",
+    );
+    o.optflag("", "no-clobber", "Do not overwrite existing output files");
+    o.optflag(
+        "",
+        "fix-extension",
+        "Append the extension of the output format to the output file name if missing",
+    );
+    o.optflag(
+        "",
+        "timezone-from-coords",
+        "Report local time, using the time zone derived from the waypoint longitude",
+    );
+    o.optflag(
+        "",
+        "verify",
+        "Read the output file back and check it holds the expected trackpoints",
+    );
+    o.optflag("", "pretty", "Indent the GPX elements, one per line");
+    o.optflag("", "compact", "Write the GPX on a single line");
+    o.optflag(
+        "",
+        "ascii-only",
+        "Replace non-ASCII characters of file and map names in the output",
+    );
+    o.optflag(
+        "q",
+        "quiet",
+        "Do not print informational messages, only warnings and errors",
+    );
+    o.optopt(
+        "",
+        "track-per",
+        "Generate a track per input file, per directory, or a single track (default)",
+        "file|dir|all",
+    );
+    o.optflag(
+        "",
+        "use-dest",
+        "Map the destination coordinates of the photos instead of where they were taken",
+    );
+    o.optflag(
+        "",
+        "coalesce-time",
+        "Replace waypoints sharing the timestamp with their mean position instead of dropping them",
+    );
+    o.optopt(
+        "",
+        "one-per-file",
+        "Keep one waypoint per input file",
+        "first|last|median",
+    );
+    o.optopt(
+        "",
+        "gap-seconds",
+        "Start a new track segment when consecutive waypoints are more than N seconds apart",
+        "N",
+    );
+    o.optopt(
+        "",
+        "max-points",
+        "Simplify every track down to at most N trackpoints, preserving its shape",
+        "N",
+    );
+    o.optopt(
+        "",
+        "simplify",
+        "Drop the trackpoints which do not move the track by more than METERS",
+        "METERS",
+    );
+    o.optopt(
+        "d",
+        "min-distance",
+        "Drop waypoints this close to the previous one, 5 by default, 0 keeps them all",
+        "METERS",
+    );
+    o.optopt(
+        "",
+        "smooth",
+        "Move each waypoint to the accuracy weighted mean position of the N waypoints around it",
+        "N",
+    );
+    o.optflag(
+        "",
+        "show-dropped",
+        "Output only the waypoints dropped as duplicates or by -d, to check the deduplication",
+    );
+    #[cfg(feature = "zip")]
+    o.optopt("", "zip", "Read photos from the ZIP archive", "FILE");
+    #[cfg(feature = "gzip")]
+    o.optflag(
+        "",
+        "gzip",
+        "Compress the output, .gz is appended to the output file name",
+    );
+    o.optflag("h", "help", "Print this help menu");
+    o
+}
+
+fn print_usage(program: &str, o: Options) {
+    let brief = format!("Usage: {} [options] exif_files_or_dirs...", program);
+    print!("{}", o.usage(&brief));
+
+    println!("\nSupported input formats:");
+    for f in Format::ALL.iter() {
+        println!("    {:<8}{}", f.name(), f.description());
+    }
+    println!("\nGPS tags read:");
+    for (tag, name) in GPS_TAG_NAMES {
+        println!("    {:#06x}  {}", tag, name);
+    }
+}
+
+// Convert number of days since Jan 1 1970 into (year, month, day).
+fn civil_from_days(days: i64) -> (i64, u64, u64) {
+    // See http://howardhinnant.github.io/date_algorithms.html#civil_from_days
+    let z = days + 719_468;
+    let era = z.div_euclid(146_097);
+    let doe = z.rem_euclid(146_097); // Day of era, [0, 146096]
+    let yoe = (doe - doe / 1460 + doe / 36524 - doe / 146_096) / 365; // [0, 399]
+    let doy = doe - (365 * yoe + yoe / 4 - yoe / 100); // [0, 365]
+    let mp = (5 * doy + 2) / 153; // [0, 11], March based
+    let day = (doy - (153 * mp + 2) / 5 + 1) as u64;
+    let month = (if mp < 10 { mp + 3 } else { mp - 9 }) as u64;
+    let year = yoe + era * 400 + if month <= 2 { 1 } else { 0 };
+
+    (year, month, day)
+}
+
+// Convert (year, month, day) into number of days since Jan 1 1970.
+fn days_from_civil(year: i64, month: u64, day: u64) -> i64 {
+    // See http://howardhinnant.github.io/date_algorithms.html#days_from_civil
+    let y = if month <= 2 { year - 1 } else { year };
+    let era = y.div_euclid(400);
+    let yoe = y.rem_euclid(400); // [0, 399]
+    let mp = (if month > 2 { month - 3 } else { month + 9 }) as i64; // [0, 11]
+    let doy = (153 * mp + 2) / 5 + day as i64 - 1; // [0, 365]
+    let doe = yoe * 365 + yoe / 4 - yoe / 100 + doy; // [0, 146096]
+
+    era * 146_097 + doe - 719_468
+}
+
+// Extensions of the files picked up from directories, other files found
+// there are skipped without a word. Files named on the command line are
+// attempted whatever their names.
+const INPUT_EXTENSIONS: [&str; 11] = [
+    "jpg", "jpeg", "tif", "tiff", "dng", "nef", "cr2", "arw", "heic", "mov", "mp4",
+];
+
+// Replace the directories among the inputs with the photos in them, in the
+// order of their names, descending into subdirectories when `recursive`.
+// Symbolic links found in the directories are ignored unless
+// `follow_symlinks`, then the directories already walked are remembered by
+// their canonical paths, so that a link to a parent does not loop forever.
+fn expand_inputs(inputs: &[PathBuf], recursive: bool, follow_symlinks: bool) -> Vec<PathBuf> {
+    let mut files: Vec<PathBuf> = Vec::new();
+    let mut visited: HashSet<PathBuf> = HashSet::new();
+
+    for input in inputs {
+        if input != Path::new("-") && input.is_dir() {
+            walk_dir(input, recursive, follow_symlinks, &mut visited, &mut files);
+        } else {
+            files.push(input.clone());
+        }
+    }
+    files
+}
+
+fn walk_dir(
+    dir: &Path,
+    recursive: bool,
+    follow_symlinks: bool,
+    visited: &mut HashSet<PathBuf>,
+    files: &mut Vec<PathBuf>,
+) {
+    if let Ok(canonical) = dir.canonicalize() {
+        if !visited.insert(canonical) {
+            return;
+        }
+    }
+    let mut entries: Vec<PathBuf> = match fs::read_dir(dir) {
+        Ok(entries) => entries.filter_map(|e| e.ok().map(|e| e.path())).collect(),
+        Err(e) => {
+            eprintln!("Warning: {}: {}, skipped", dir.display(), e);
+            return;
+        }
+    };
+
+    entries.sort();
+    for path in entries {
+        let is_link = fs::symlink_metadata(&path).is_ok_and(|m| m.file_type().is_symlink());
+        if is_link && !follow_symlinks {
+            continue;
+        }
+        if path.is_dir() {
+            if recursive {
+                walk_dir(&path, recursive, follow_symlinks, visited, files);
+            }
+        } else if path
+            .extension()
+            .is_some_and(|ext| INPUT_EXTENSIONS.iter().any(|e| ext.eq_ignore_ascii_case(e)))
+        {
+            files.push(path);
+        }
+    }
+}
+
+// The map name, when not given, is the name of the directory the first photo
+// is in, or, failing that, the program name with the current date.
+fn default_map_name(files: &[PathBuf]) -> String {
+    let dir = files
+        .first()
+        .and_then(|f| f.canonicalize().ok())
+        .and_then(|p| p.parent().and_then(|d| d.file_name()).map(|d| d.to_owned()));
+
+    if let Some(dir) = dir {
+        return dir.to_string_lossy().to_string();
+    }
+
+    let now = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map_or(0, |d| d.as_secs());
+    let (year, month, day) = civil_from_days((now / (24 * 60 * 60)) as i64);
+    format!("exifgeo {}-{:02}-{:02}", year, month, day)
+}
+
+// Drop waypoints not passing the filter and report how many were dropped.
+fn filter_waypoints<F>(waypoints: &mut Vec<GpsInfo>, keep: F, why: &str, opts: &OutputOpts)
+where
+    F: Fn(&GpsInfo) -> bool,
+{
+    let before = waypoints.len();
+
+    waypoints.retain(keep);
+    if waypoints.len() != before && !opts.quiet {
+        eprintln!("Dropped {} waypoint(s) {}", before - waypoints.len(), why);
+    }
+}
+
+// Parse the "lat1,lon1;lat2,lon2;..." vertices of a polygon, at least three
+// of them are needed. Every edge is taken to be the shorter way around, so
+// the longitudes of the vertices are unwrapped: a polygon crossing the
+// antimeridian ends up with some of them beyond 180.
+fn parse_polygon(text: &str) -> Result<Vec<(f64, f64)>> {
+    let mut polygon: Vec<(f64, f64)> = Vec::new();
+
+    for vertex in text.split(';').filter(|v| !v.trim().is_empty()) {
+        let coords: Vec<Option<f64>> = vertex.split(',').map(|c| c.trim().parse().ok()).collect();
+        match coords[..] {
+            [Some(lat), Some(longt)] if lat.abs() <= 90.0 && longt.abs() <= 180.0 => {
+                let longt = match polygon.last() {
+                    Some(&(_, prev)) => prev + wrap_longitude(longt - prev),
+                    None => longt,
+                };
+                polygon.push((lat, longt))
+            }
+            _ => {
+                eprintln!("Error: bad --polygon vertex {}", vertex);
+                return Err(Error::from(ErrorKind::InvalidData));
+            }
+        }
+    }
+    if polygon.len() < 3 {
+        eprintln!("Error: --polygon needs at least three vertices");
+        return Err(Error::from(ErrorKind::InvalidData));
+    }
+    Ok(polygon)
+}
+
+// Ray casting: a point is inside the polygon if a ray going from it east
+// crosses the edges an odd number of times. Coordinates are treated as plane
+// ones, the longitude of the point is unwrapped the same way as the vertices
+// of the polygon, see parse_polygon(). Polygons around a pole are not
+// supported.
+fn in_polygon(lat: f64, longt: f64, polygon: &[(f64, f64)]) -> bool {
+    let longt = polygon[0].1 + wrap_longitude(longt - polygon[0].1);
+    let mut inside = false;
+    let mut prev = polygon[polygon.len() - 1];
+
+    for &vertex in polygon {
+        let ((lat1, lon1), (lat2, lon2)) = (prev, vertex);
+        if (lat1 > lat) != (lat2 > lat) {
+            let crossing = lon1 + (lat - lat1) * (lon2 - lon1) / (lat2 - lat1);
+            if longt < crossing {
+                inside = !inside;
+            }
+        }
+        prev = vertex;
+    }
+    inside
+}
+
+// getopts only takes UTF-8 arguments, so each one which is not is replaced by
+// a placeholder referring to it by index.
+fn utf8_args(args: &[OsString]) -> Vec<String> {
+    args.iter()
+        .enumerate()
+        .map(|(i, a)| match a.to_str() {
+            Some(s) => s.to_string(),
+            None => format!("\0{}", i),
+        })
+        .collect()
+}
+
+// Map a parsed argument back to the path it was given as. A non-UTF-8 option
+// value is only recognized as a separate argument, not after '='.
+fn arg_path(value: &str, args: &[OsString]) -> PathBuf {
+    match value
+        .strip_prefix('\0')
+        .and_then(|i| i.parse::<usize>().ok())
+        .and_then(|i| args.get(i))
+    {
+        Some(original) => PathBuf::from(original),
+        None => PathBuf::from(value),
+    }
+}
+
+// Retrieve the value of an optional numeric command line option.
+fn opt_num<T: str::FromStr>(matches: &Matches, name: &str) -> Result<Option<T>> {
+    match matches.opt_get::<T>(name) {
+        Ok(v) => Ok(v),
+        Err(_) => {
+            eprintln!("Error: bad --{} value", name);
+            Err(Error::from(ErrorKind::InvalidData))
+        }
+    }
+}
+
+// Name of the output format, the --format flag takes precedence over the
+// EXIFGEO_FORMAT environment variable, passed in as `env_format`.
+fn map_format(matches: &Matches, env_format: Option<String>) -> Option<String> {
+    matches
+        .opt_str("format")
+        .or_else(|| env_format.filter(|f| !f.is_empty()))
+}
+
+// Accessors of the GPS data for the library users.
+impl GpsInfo {
+    pub fn file_name(&self) -> &str {
+        &self.file_name
+    }
+
+    pub fn lat(&self) -> f64 {
+        self.lat
+    }
+
+    pub fn lon(&self) -> f64 {
+        self.longt
+    }
+
+    // Meters above sea level.
+    pub fn alt(&self) -> Option<f64> {
+        self.alt
+    }
+
+    // Seconds since Jan 1 1970, UTC.
+    pub fn time(&self) -> Option<u64> {
+        self.time
+    }
+}
+
+// Geotag of the photo or video, the format is detected. Files without one end
+// up with an ErrorKind::Other error, as they do in the command line tool.
+pub fn parse_file(path: &str) -> Result<GpsInfo> {
+    let mut waypoints: Vec<GpsInfo> = Vec::new();
+
+    read_geotags(path, &mut waypoints, None)?;
+    waypoints.pop().ok_or_else(|| Skip::NoGps.into())
+}
+
+// parse_file() calling `on_tag` with the tag and the value of every entry of
+// the Exif IFDs it reads: IFD0 and the IFDs chained to it, the Exif sub-IFD and
+// the GPS IFD. Values of types other than those of TagValue are Other. The
+// tags of the GPS IFD are numbered from 0, below those of the other IFDs.
+// Videos have no Exif data and leave `on_tag` uncalled.
+pub fn parse_file_with<F: FnMut(u16, &TagValue)>(path: &str, mut on_tag: F) -> Result<GpsInfo> {
+    let mut waypoints: Vec<GpsInfo> = Vec::new();
+    let mut f = File::open(path)?;
+
+    parse_stream_with(&mut f, path, &mut waypoints, None, Some(&mut on_tag)).map_err(damaged)?;
+    waypoints.pop().ok_or_else(|| Skip::NoGps.into())
+}
+
+// Maps of the waypoints in the --format formats, named `map_name`. The
+// waypoints are written in the given order, they are not sorted or
+// deduplicated as in the command line tool.
+pub fn write_gpx(waypoints: &[GpsInfo], map_name: &str) -> Result<Vec<u8>> {
+    write_map(&Gpx, waypoints, map_name)
+}
+
+pub fn write_kml(waypoints: &[GpsInfo], map_name: &str) -> Result<Vec<u8>> {
+    write_map(&Kml, waypoints, map_name)
+}
+
+pub fn write_csv(waypoints: &[GpsInfo], map_name: &str) -> Result<Vec<u8>> {
+    write_map(&Csv, waypoints, map_name)
+}
+
+pub fn write_json(waypoints: &[GpsInfo], map_name: &str) -> Result<Vec<u8>> {
+    write_map(&Json, waypoints, map_name)
+}
+
+// Map of the waypoints in any format, the built-in ones as well as those of
+// the library users.
+pub fn write_map(
+    format: &dyn OutputFormat,
+    waypoints: &[GpsInfo],
+    map_name: &str,
+) -> Result<Vec<u8>> {
+    let opts = OutputOpts::new(map_name);
+    let mut av = AV::new();
+
+    format.write(&mut av, waypoints, &opts)?;
+    Ok(av)
+}
+
+// The command line tool, parsing the arguments of the process.
+pub fn run() -> Result<()> {
+    // Arguments which are not valid UTF-8 would make env::args() panic.
+    let os_args: Vec<OsString> = env::args_os().collect();
+    let args = utf8_args(&os_args);
+    let path = |value: &str| arg_path(value, &os_args);
+    let o = prepare_opts();
+
+    let matches = match o.parse(&args[1..]) {
+        Ok(m) => m,
+        Err(f) => {
+            eprintln!("{}", f);
+            return Err(Error::from(ErrorKind::InvalidData));
+        }
+    };
+
+    if matches.opt_present("h") {
+        print_usage(&path(&args[0]).to_string_lossy(), o);
+        return Ok(());
+    }
+
+    let format = match matches.opt_str("force-format") {
+        None => None,
+        Some(v) => match Format::ALL.iter().find(|f| f.name() == v) {
+            Some(f) => Some(*f),
+            None => {
+                eprintln!("Error: bad --force-format value {}", v);
+                return Err(Error::from(ErrorKind::InvalidData));
+            }
+        },
+    };
+
+    if matches.opt_present("selftest") {
+        return if self_test() {
+            Ok(())
+        } else {
+            Err(Error::from(ErrorKind::InvalidData))
+        };
+    }
+
+    let paths: Vec<PathBuf> = matches.free.iter().map(|f| path(f)).collect();
+    let inputs = expand_inputs(
+        &paths,
+        matches.opt_present("recursive"),
+        matches.opt_present("follow-symlinks"),
+    );
+
+    if matches.opt_present("dump") {
+        for f in &inputs {
+            if let Err(e) = dump_file(f, format) {
+                eprintln!("{}: {}", f.display(), e);
+            }
+        }
+        return Ok(());
+    }
+
+    let map_name = match matches.opt_str("m") {
+        Some(name) => path(&name).to_string_lossy().into_owned(),
+        None => default_map_name(&inputs),
+    };
+
+    let max_accuracy: Option<f64> = opt_num(&matches, "max-accuracy")?;
+    let min_satellites: Option<u32> = opt_num(&matches, "min-satellites")?;
+    let max_error: Option<f64> = opt_num(&matches, "max-error")?;
+    let polygon = match matches.opt_str("polygon") {
+        Some(text) => Some(parse_polygon(&text)?),
+        None => None,
+    };
+    let mut opts = OutputOpts::new(&map_name);
+
+    if let Some(name) = map_format(&matches, env::var("EXIFGEO_FORMAT").ok()) {
+        opts.format = match find_format(&name) {
+            Some(format) => format,
+            None => {
+                eprintln!("Error: unknown output format {}", name);
+                return Err(Error::from(ErrorKind::InvalidData));
+            }
+        };
+    }
+    if let Some(rgb) = matches.opt_str("kml-color") {
+        opts.kml_color = match kml_color(&rgb) {
+            Some(color) => Some(color),
+            None => {
+                eprintln!("Error: bad --kml-color value {}, expected RRGGBB", rgb);
+                return Err(Error::from(ErrorKind::InvalidData));
+            }
+        };
+    }
+    opts.kml_width = opt_num(&matches, "kml-width")?;
+    if opts.kml_width.is_some_and(|w| w.is_nan() || w <= 0.0) {
+        eprintln!("Error: --kml-width must be positive");
+        return Err(Error::from(ErrorKind::InvalidData));
+    }
+    opts.merge_tolerance = opt_num(&matches, "merge-tolerance")?;
+    opts.smooth = opt_num(&matches, "smooth")?;
+    opts.gap_seconds = opt_num(&matches, "gap-seconds")?.filter(|&n| n > 0);
+    if let Some(meters) = opt_num::<f64>(&matches, "min-distance")? {
+        if meters.is_nan() || meters < 0.0 {
+            eprintln!("Error: --min-distance must not be negative");
+            return Err(Error::from(ErrorKind::InvalidData));
+        }
+        opts.min_distance = meters;
+    }
+    opts.max_points = opt_num(&matches, "max-points")?;
+    if opts.max_points.is_some_and(|n| n < 2) {
+        eprintln!("Error: --max-points must keep at least the two ends of a track");
+        return Err(Error::from(ErrorKind::InvalidData));
+    }
+    opts.simplify = opt_num(&matches, "simplify")?;
+    if opts.simplify.is_some_and(|m| m.is_nan() || m <= 0.0) {
+        eprintln!("Error: --simplify tolerance must be positive");
+        return Err(Error::from(ErrorKind::InvalidData));
+    }
+    if opts.smooth == Some(0) {
+        eprintln!("Error: --smooth window must be at least one waypoint");
+        return Err(Error::from(ErrorKind::InvalidData));
+    }
+    opts.flatten = matches.opt_present("flatten");
+    opts.no_clobber = matches.opt_present("no-clobber");
+    opts.quiet = matches.opt_present("q");
+    opts.show_dropped = matches.opt_present("show-dropped");
+    opts.ascii_only = matches.opt_present("ascii-only");
+    if matches.opt_present("pretty") && matches.opt_present("compact") {
+        eprintln!("Error: --pretty and --compact are mutually exclusive");
+        return Err(Error::from(ErrorKind::InvalidData));
+    }
+    if matches.opt_present("pretty") {
+        opts.layout = Layout::Pretty;
+    } else if matches.opt_present("compact") {
+        opts.layout = Layout::Compact;
+    }
+    opts.coalesce_time = matches.opt_present("coalesce-time");
+    if matches.opt_present("timezone-from-coords") {
+        opts.offset_for = Some(nautical_offset);
+    }
+    if let Some(precision) = opt_num(&matches, "precision")? {
+        if precision > 9 {
+            eprintln!("Error: --precision must be between 0 and 9");
+            return Err(Error::from(ErrorKind::InvalidData));
+        }
+        opts.precision = precision;
+    }
+    match matches.opt_str("track-per").as_deref() {
+        None | Some("all") => {}
+        Some("file") => opts.track_per = TrackPer::File,
+        Some("dir") => opts.track_per = TrackPer::Dir,
+        Some(v) => {
+            eprintln!("Error: bad --track-per value {}", v);
+            return Err(Error::from(ErrorKind::InvalidData));
+        }
+    }
+    match matches.opt_str("one-per-file").as_deref() {
+        None => {}
+        Some("first") => opts.one_per_file = Some(PerFile::First),
+        Some("last") => opts.one_per_file = Some(PerFile::Last),
+        Some("median") => opts.one_per_file = Some(PerFile::Median),
+        Some(v) => {
+            eprintln!("Error: bad --one-per-file value {}", v);
+            return Err(Error::from(ErrorKind::InvalidData));
+        }
+    }
+    match matches.opt_str("altitude-source").as_deref() {
+        None | Some("gps") => {}
+        Some(source @ ("relative" | "absolute")) => eprintln!(
+            "Warning: {} altitudes are not supported, using the GPS altitude",
+            source
+        ),
+        Some(v) => {
+            eprintln!("Error: bad --altitude-source value {}", v);
+            return Err(Error::from(ErrorKind::InvalidData));
+        }
+    }
+    match matches.opt_str("gpx-version").as_deref() {
+        None | Some("1.1") => {}
+        Some("1.0") => opts.gpx_version = GpxVersion::V1_0,
+        Some(v) => {
+            eprintln!("Error: unsupported GPX version {}", v);
+            return Err(Error::from(ErrorKind::InvalidData));
+        }
+    }
+    if let Some(template) = matches.opt_str("template") {
+        opts.template = Some(parse_template(&template)?);
+    }
+    if matches.opt_present("verify") && (opts.template.is_some() || opts.format.name() != "gpx") {
+        eprintln!("Error: only GPX output can be verified");
+        return Err(Error::from(ErrorKind::InvalidData));
+    }
+    if matches.opt_present("verify") && matches.opt_present("split-output") {
+        eprintln!("Error: --verify does not support --split-output");
+        return Err(Error::from(ErrorKind::InvalidData));
+    }
+    opts.obfuscate = opt_num(&matches, "obfuscate")?;
+    if opts.obfuscate.is_some_and(|m| m.is_nan() || m <= 0.0) {
+        eprintln!("Error: --obfuscate grid size must be positive");
+        return Err(Error::from(ErrorKind::InvalidData));
+    }
+
+    // The limit is on the files attempted, whether they have geotags or not.
+    let max_files: Option<usize> = opt_num(&matches, "max-files")?;
+    let mut files: &[PathBuf] = &inputs;
+    if let Some(max_files) = max_files {
+        if max_files < files.len() {
+            if !opts.quiet {
+                eprintln!("Processing {} of {} files", max_files, files.len());
+            }
+            files = &files[..max_files];
+        }
+    }
+
+    let strict = matches.opt_present("strict");
+    let trailer = opt_num::<u64>(&matches, "scan-trailer")?.map(|kb| kb.saturating_mul(1024));
+    let mut waypoints = if matches.opt_present("stats") {
+        let batch = parse_batch(files, format, trailer);
+
+        eprintln!(
+            "{} parsed, {} without geotags, {} failed; I/O {:.3} s, parsing {:.3} s",
+            batch.parsed,
+            batch.no_gps,
+            batch.errored,
+            batch.io_time.as_secs_f64(),
+            batch.parse_time.as_secs_f64()
+        );
+        if strict && batch.parsed != files.len() {
+            return Err(Error::from(ErrorKind::InvalidData));
+        }
+        batch.waypoints
+    } else {
+        parse_files(files, strict, format, trailer)?
+    };
+
+    #[cfg(feature = "zip")]
+    if let Some(zip) = matches.opt_str("zip") {
+        parse_zip(&path(&zip), &mut waypoints, strict)?;
+    }
+
+    if matches.opt_present("use-dest") {
+        filter_waypoints(
+            &mut waypoints,
+            |w| w.dest.is_some(),
+            "without destination",
+            &opts,
+        );
+        for w in waypoints.iter_mut() {
+            if let Some((lat, longt)) = w.dest {
+                w.lat = lat;
+                w.longt = longt;
+            }
+        }
+    }
+
+    let link_base = matches
+        .opt_str("link-base")
+        .map(|base| path(&base).to_string_lossy().into_owned());
+    if link_base.is_some() || matches.opt_present("links") {
+        for w in waypoints.iter_mut() {
+            w.link = photo_link(&w.file_name, link_base.as_deref());
+        }
+    }
+
+    if let Some(gpx) = matches.opt_str("merge") {
+        read_gpx(&path(&gpx), &mut waypoints, &opts)?;
+    }
+    for (i, w) in waypoints.iter_mut().enumerate() {
+        w.seq = i;
+    }
+
+    // Waypoints which do not report the filtered property are kept, except
+    // when differential correction is required.
+    if let Some(limit) = max_accuracy {
+        filter_waypoints(
+            &mut waypoints,
+            |w| w.h_error.is_none_or(|e| e <= limit),
+            &format!("less accurate than {} m", limit),
+            &opts,
+        );
+    }
+    if let Some(limit) = max_error {
+        filter_waypoints(
+            &mut waypoints,
+            |w| w.quality().is_none_or(|e| e <= limit),
+            &format!("with estimated error above {} m", limit),
+            &opts,
+        );
+    }
+    if let Some(limit) = min_satellites {
+        filter_waypoints(
+            &mut waypoints,
+            |w| w.satellites.is_none_or(|s| s >= limit),
+            &format!("fixed with fewer than {} satellites", limit),
+            &opts,
+        );
+    }
+    if let Some(polygon) = &polygon {
+        filter_waypoints(
+            &mut waypoints,
+            |w| in_polygon(w.lat, w.longt, polygon),
+            "outside the polygon",
+            &opts,
+        );
+    }
+    if matches.opt_present("require-differential") {
+        filter_waypoints(
+            &mut waypoints,
+            |w| w.differential == Some(true),
+            "without differential correction",
+            &opts,
+        );
+    }
+
+    if waypoints.is_empty() {
+        if !opts.quiet {
+            eprintln!("No geotags found in input file(s)");
+        }
+        return Ok(());
+    }
+
+    if let Some(dir) = matches.opt_str("split-output") {
+        return split_output(&waypoints, &path(&dir), &opts);
+    }
+
+    let mut buf = AV::new();
+    print_xml(&mut waypoints, &mut buf, &opts)?;
+
+    let finished = finish_output(&buf, &opts, opts.template.is_none() && opts.format.is_xml());
+    let bytes: &[u8] = &finished;
+    #[cfg(feature = "gzip")]
+    let gzip = matches.opt_present("gzip");
+    #[cfg(not(feature = "gzip"))]
+    let gzip = false;
+    #[cfg(feature = "gzip")]
+    let compressed;
+    #[cfg(feature = "gzip")]
+    let bytes: &[u8] = if gzip {
+        compressed = gzip::compress(bytes);
+        &compressed
+    } else {
+        bytes
+    };
+    let output = match matches.opt_str("o") {
+        Some(name) => {
+            let mut name = path(&name);
+            if gzip && name.extension().is_some_and(|e| e == "gz") {
+                name.set_extension("");
+            }
+            if let Some(extension) = output_extension(&opts) {
+                name = check_extension(name, extension, matches.opt_present("fix-extension"));
+            }
+            if gzip {
+                let mut gz = name.into_os_string();
+                gz.push(".gz");
+                name = PathBuf::from(gz);
+            }
+            write_output(&name, bytes, &opts)?;
+            Some(name)
+        }
+        None => {
+            let mut stdout = io::stdout();
+            stdout.write_all(bytes)?;
+            if !gzip {
+                writeln!(stdout)?;
+            }
+            // Otherwise whatever is still buffered is flushed at exit, with
+            // any error ignored.
+            stdout.flush()?;
+            None
+        }
+    };
+
+    if matches.opt_present("verify") {
+        // What ended up in the output file is checked, the console output
+        // cannot be read back.
+        let written = match &output {
+            Some(name) => fs::read(name)?,
+            None => bytes.to_vec(),
+        };
+        #[cfg(feature = "gzip")]
+        let written = if gzip {
+            gzip::decompress(&written).inspect_err(|e| {
+                eprintln!("Error: verification failed, {}", e);
+            })?
+        } else {
+            written
+        };
+        verify_output(&String::from_utf8_lossy(&written), &waypoints, &opts)?;
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::fs;
+
+    #[test]
+    fn test_parse_file() -> Result<()> {
+        let mut waypoints: Vec<GpsInfo> = Vec::new();
+
+        for i in 0..4 {
+            let test_data: String = format!("src/test_data/test{}.jpg", i);
+
+            read_geotags(&test_data, &mut waypoints, None)?;
+        }
+
+        let mut buf: AV = AV::new();
+        print_xml(&mut waypoints, &mut buf, &OutputOpts::new("Test map"))?;
+
+        let expected: String =
+            fs::read_to_string("src/test_data/result.txt").expect("Failed to read result.txt");
+
+        if expected == std::str::from_utf8(&buf).unwrap() {
+            Ok(())
+        } else {
+            Err(Error::from(ErrorKind::InvalidData))
+        }
+    }
+
+    #[test]
+    fn test_library() -> Result<()> {
+        let w = parse_file("src/test_data/test1.jpg")?;
+        assert_eq!(w.file_name(), "src/test_data/test1.jpg");
+        assert!((w.lat() - 66.04576).abs() < 1e-5);
+        assert!((w.lon() + 17.34345).abs() < 1e-5);
+        assert_eq!(format_time(w.time().unwrap()), "2021-07-09T13:33:57Z");
+        assert!(w.alt().is_some_and(|alt| (alt - 70.54).abs() < 0.01));
+
+        let e = parse_file("README.md").err().map(|e| e.kind());
+        assert_eq!(e, Some(ErrorKind::Other));
+
+        let waypoints = [w, parse_file("src/test_data/test0.jpg")?];
+        assert!((waypoints[1].lat() + 33.85744).abs() < 1e-5);
+        assert!((waypoints[1].lon() - 151.23981).abs() < 1e-5);
+        let csv = write_csv(&waypoints, "Test map")?;
+        assert_eq!(
+            String::from_utf8(csv).unwrap(),
+            "lat,lon,alt,time\n\
+             66.04576,-17.34345,70.5,2021-07-09T13:33:57Z\n\
+             -33.85744,151.23981,60.5,2020-01-21T06:10:56Z\n"
+        );
+        let mut parsed: Vec<GpsInfo> = Vec::new();
+        let gpx = write_gpx(&waypoints, "Test map")?;
+        parse_gpx(&String::from_utf8(gpx).unwrap(), "test.gpx", &mut parsed)?;
+        assert_eq!(parsed.len(), 2);
+        assert!(String::from_utf8(write_kml(&waypoints, "Test map")?)
+            .unwrap()
+            .contains("<coordinates>-17.34345,66.04576,70.5 151.23981,-33.85744,60.5"));
+        assert!(write_json(&waypoints, "Test map")?.starts_with(b"[\n{\"lat\": 66.04576"));
+        Ok(())
+    }
+
+    #[test]
+    fn test_tag_hook() -> Result<()> {
+        let mut tags: HashMap<u16, String> = HashMap::new();
+        let mut latitude: Vec<f64> = Vec::new();
+
+        let w = parse_file_with("src/test_data/test1.jpg", |tag, value| {
+            if let Some(text) = value.text() {
+                tags.insert(tag, text.to_string());
+            }
+            if tag == LAT_V {
+                latitude = value.rationals().to_vec();
+            }
+        })?;
+        assert!((w.lat - 66.04576).abs() < 1e-5);
+        // Make and Model from IFD0, the time taken from the Exif sub-IFD.
+        assert_eq!(tags[&0x010f], "Google");
+        assert_eq!(tags[&0x0110], "Pixel 4a");
+        assert_eq!(tags[&DATE_TIME_ORIGINAL], "2021:07:09 13:34:44");
+        assert_eq!(latitude.len(), 3);
+
+        // The hook does not change what files are skipped.
+        let e = parse_file_with("README.md", |_, _| panic!("no tags expected"));
+        assert_eq!(e.err().map(|e| e.kind()), Some(ErrorKind::Other));
+        Ok(())
+    }
+
+    #[test]
+    fn test_self_test() {
+        assert!(self_test());
+    }
+
+    #[test]
+    fn test_layout() -> Result<()> {
+        let mut waypoints: Vec<GpsInfo> = Vec::new();
+        let mut opts = OutputOpts::new("Test map");
+
+        for i in 0..4 {
+            read_geotags(format!("src/test_data/test{}.jpg", i), &mut waypoints, None)?;
+        }
+        let mut buf: AV = AV::new();
+        print_xml(&mut waypoints, &mut buf, &opts)?;
+
+        opts.layout = Layout::Pretty;
+        let pretty = String::from_utf8(finish_output(&buf, &opts, true).into_owned()).unwrap();
+        assert!(pretty.contains(
+            "\n      <trkpt lat=\"-54.81007\" lon=\"-68.31601\">\n        \
+             <ele>18.0</ele>\n        \
+             <time>2018-01-27T13:25:00Z</time>\n      </trkpt>\n"
+        ));
+        opts.layout = Layout::Compact;
+        let compact = String::from_utf8(finish_output(&buf, &opts, true).into_owned()).unwrap();
+        assert_eq!(compact.lines().count(), 1);
+
+        // Either way it is the same map.
+        for text in [pretty, compact] {
+            let mut parsed: Vec<GpsInfo> = Vec::new();
+            parse_gpx(&text, "test.gpx", &mut parsed)?;
+            assert_eq!(parsed.len(), 4);
+            assert_eq!(parsed[3].time, waypoints[3].time);
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn test_verify_output() -> Result<()> {
+        let mut waypoints: Vec<GpsInfo> = Vec::new();
+        let mut opts = OutputOpts::new("Test map");
+
+        for i in 0..4 {
+            read_geotags(format!("src/test_data/test{}.jpg", i), &mut waypoints, None)?;
+        }
+        opts.quiet = true;
+        let mut buf: AV = AV::new();
+        print_xml(&mut waypoints, &mut buf, &opts)?;
+        let expected = &waypoints;
+        let text = std::str::from_utf8(&buf).unwrap();
+        verify_output(text, expected, &opts)?;
+
+        // A trackpoint moved by more than the precision, or missing.
+        let moved = text.replace("-54.81007", "-54.81017");
+        assert!(verify_output(&moved, expected, &opts).is_err());
+        assert!(verify_output(text, &expected[1..], &opts).is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn test_fixtures() -> Result<()> {
+        // test5.jpg has no timestamp, test9.jpg is big endian and test10.jpg
+        // was taken below the sea level.
+        let mut waypoints: Vec<GpsInfo> = Vec::new();
+
+        for i in [5, 9, 10] {
+            read_geotags(format!("src/test_data/test{}.jpg", i), &mut waypoints, None)?;
+        }
+
+        let mut buf: AV = AV::new();
+        print_xml(&mut waypoints, &mut buf, &OutputOpts::new("Test map"))?;
+
+        let expected: String =
+            fs::read_to_string("src/test_data/fixtures.txt").expect("Failed to read fixtures.txt");
+        assert_eq!(expected, std::str::from_utf8(&buf).unwrap());
+
+        // The generated GPX reads back into the same track.
+        let mut parsed: Vec<GpsInfo> = Vec::new();
+        parse_gpx(&expected, "fixtures.txt", &mut parsed)?;
+        let mut again: AV = AV::new();
+        print_xml(&mut parsed, &mut again, &OutputOpts::new("Test map"))?;
+        assert_eq!(expected, std::str::from_utf8(&again).unwrap());
+        Ok(())
+    }
+
+    #[test]
+    fn test_parse_gpx() -> Result<()> {
+        let expected: String =
+            fs::read_to_string("src/test_data/result.txt").expect("Failed to read result.txt");
+        let mut waypoints: Vec<GpsInfo> = Vec::new();
+
+        parse_gpx(&expected, "result.txt", &mut waypoints)?;
+        assert_eq!(waypoints.len(), 4);
+
+        let mut buf: AV = AV::new();
+        print_xml(&mut waypoints, &mut buf, &OutputOpts::new("Test map"))?;
+        assert_eq!(expected, std::str::from_utf8(&buf).unwrap());
+        Ok(())
+    }
+
+    #[test]
+    fn test_parse_bytes() -> Result<()> {
+        let bytes = fs::read("src/test_data/test4.jpg")?;
+        let mut waypoints: Vec<GpsInfo> = Vec::new();
+
+        read_geotags("src/test_data/test4.jpg", &mut waypoints, None)?;
+        let parsed = parse_bytes(&bytes, "test4.jpg", None)?;
+        assert_eq!(parsed.len(), 1);
+        assert_eq!(parsed[0].file_name, "test4.jpg");
+        assert_eq!(parsed[0].lat, waypoints[0].lat);
+        assert_eq!(parsed[0].longt, waypoints[0].longt);
+        assert_eq!(parsed[0].time, waypoints[0].time);
+        Ok(())
+    }
+
+    #[test]
+    fn test_snap_to_grid() {
+        let mut a = GpsInfo::new();
+        let mut b = GpsInfo::new();
+
+        a.lat = 60.000_1;
+        a.longt = 10.000_1;
+        b.lat = 60.000_2;
+        b.longt = 10.000_3;
+        let original = GpsInfo {
+            lat: a.lat,
+            longt: a.longt,
+            ..GpsInfo::new()
+        };
+
+        a.snap_to_grid(1000.0);
+        b.snap_to_grid(1000.0);
+        assert_eq!((a.lat, a.longt), (b.lat, b.longt));
+        // Never moved further than half the cell diagonal.
+        assert!(a.distance_from(&original) <= 1000.0 * 0.75);
+    }
+
+    #[test]
+    fn test_count_satellites() {
+        assert_eq!(count_satellites("7"), Some(7));
+        assert_eq!(count_satellites("05 12 17, 23"), Some(4));
+        assert_eq!(count_satellites(""), None);
+        assert_eq!(count_satellites("GPS+GLONASS"), None);
+    }
+
+    #[test]
+    fn test_no_timestamp() -> Result<()> {
+        // test5.jpg carries coordinates only.
+        let mut waypoints: Vec<GpsInfo> = Vec::new();
+
+        read_geotags("src/test_data/test5.jpg", &mut waypoints, None)?;
+        read_geotags("src/test_data/test1.jpg", &mut waypoints, None)?;
+        assert_eq!(waypoints[0].time, None);
+
+        let mut buf: AV = AV::new();
+        print_xml(&mut waypoints, &mut buf, &OutputOpts::new("Test map"))?;
+        assert_eq!(waypoints[0].file_name, "src/test_data/test1.jpg");
+        let output = std::str::from_utf8(&buf).unwrap();
+        assert_eq!(output.matches("<trkpt").count(), 2);
+        assert_eq!(output.matches("<time>").count(), 1);
+        Ok(())
+    }
+
+    #[test]
+    fn test_gps_in_ifd1() -> Result<()> {
+        // test6.jpg has no GPS pointer in IFD0, only in IFD1.
+        let mut waypoints: Vec<GpsInfo> = Vec::new();
+
+        read_geotags("src/test_data/test6.jpg", &mut waypoints, None)?;
+        assert_eq!(waypoints.len(), 1);
+        assert!((waypoints[0].lat - 37.7749).abs() < 1e-6);
+        assert!((waypoints[0].longt + 122.4194).abs() < 1e-6);
+        Ok(())
+    }
+
+    #[test]
+    fn test_photo_link() {
+        assert_eq!(
+            photo_link("a/IMG 1&2.jpg", Some("https://example.com/p")),
+            Some((
+                "https://example.com/p/IMG%201%262.jpg".to_string(),
+                "IMG 1&2.jpg".to_string()
+            ))
+        );
+        assert_eq!(
+            photo_link("IMG.jpg", Some("photos/")).map(|l| l.0),
+            Some("photos/IMG.jpg".to_string())
+        );
+        assert_eq!(photo_link("-", None), None);
+
+        let (href, _) = photo_link("src/test_data/test1.jpg", None).unwrap();
+        assert!(href.starts_with("file:///"));
+        assert!(href.ends_with("/src/test_data/test1.jpg"));
+    }
+
+    #[test]
+    fn test_differential() -> Result<()> {
+        // test7.jpg reports differential correction applied.
+        let mut waypoints: Vec<GpsInfo> = Vec::new();
+
+        read_geotags("src/test_data/test7.jpg", &mut waypoints, None)?;
+        read_geotags("src/test_data/test1.jpg", &mut waypoints, None)?;
+        assert_eq!(waypoints[0].differential, Some(true));
+        assert_eq!(waypoints[1].differential, None);
+
+        let mut buf: AV = AV::new();
+        print_xml(&mut waypoints, &mut buf, &OutputOpts::new("Test map"))?;
+        let output = std::str::from_utf8(&buf).unwrap();
+        assert!(output
+            .contains("<extensions><exifgeo:differential>1</exifgeo:differential></extensions>"));
+
+        let mut parsed: Vec<GpsInfo> = Vec::new();
+        parse_gpx(output, "test.gpx", &mut parsed)?;
+        assert_eq!(parsed[0].differential, Some(true));
+        Ok(())
+    }
+
+    #[test]
+    fn test_parse_batch() {
+        let paths: Vec<PathBuf> = ["test1.jpg", "test5.jpg", "result.txt", "missing.jpg"]
+            .iter()
+            .map(|name| Path::new("src/test_data").join(name))
+            .collect();
+        let batch = parse_batch(&paths, None, None);
+
+        assert_eq!(batch.waypoints.len(), 2);
+        assert_eq!(batch.parsed, 2);
+        assert_eq!(batch.no_gps, 0);
+        assert_eq!(batch.errored, 2);
+    }
+
+    #[test]
+    fn test_scan_trailer() -> Result<()> {
+        // test19.jpg is test1.jpg with the Exif segment moved past EOI.
+        let files = ["src/test_data/test19.jpg".to_string()];
+        assert!(parse_files(&files, false, None, None)?.is_empty());
+        assert!(parse_files(&files, false, None, Some(64))?.is_empty());
+
+        let mut waypoints = parse_files(&files, false, None, Some(64 * 1024))?;
+        read_geotags("src/test_data/test1.jpg", &mut waypoints, None)?;
+        assert_eq!(waypoints.len(), 2);
+        assert_eq!(waypoints[0].lat, waypoints[1].lat);
+        assert_eq!(waypoints[0].time, waypoints[1].time);
+
+        let batch = parse_batch(&[PathBuf::from(&files[0])], None, Some(64 * 1024));
+        assert_eq!(batch.parsed, 1);
+        assert_eq!(batch.waypoints[0].lat, waypoints[0].lat);
+        Ok(())
+    }
+
+    #[test]
+    fn test_parallel_map() -> Result<()> {
+        let items: Vec<u64> = (0..1000).collect();
+        assert_eq!(
+            parallel_map(&items, |i| i * 2),
+            (0..1000).map(|i| i * 2).collect::<Vec<u64>>()
+        );
+        assert!(parallel_map(&[] as &[u64], |i| *i).is_empty());
+
+        // The waypoints come in the order of the files, the file without
+        // usable geotags is skipped.
+        let files: Vec<String> = [3, 11, 0, 1, 2, 4]
+            .iter()
+            .map(|i| format!("src/test_data/test{}.jpg", i))
+            .collect();
+        let mut expected: Vec<GpsInfo> = Vec::new();
+        for f in files.iter() {
+            let _ = read_geotags(f, &mut expected, None);
+        }
+        let waypoints = parse_files(&files, false, None, None)?;
+        assert_eq!(waypoints.len(), expected.len());
+        for (w, e) in waypoints.iter().zip(expected.iter()) {
+            assert_eq!((&w.file_name, w.lat, w.time), (&e.file_name, e.lat, e.time));
+        }
+        assert!(parse_files(&files, true, None, None).is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn test_multiple_app1() -> Result<()> {
+        // test20.jpg is test1.jpg with an XMP APP1 segment and an Exif one
+        // without the GPS IFD inserted before its Exif segment.
+        let mut waypoints: Vec<GpsInfo> = Vec::new();
+
+        read_geotags("src/test_data/test1.jpg", &mut waypoints, None)?;
+        read_geotags("src/test_data/test20.jpg", &mut waypoints, None)?;
+        assert_eq!(waypoints.len(), 2);
+        assert_eq!(
+            (waypoints[0].lat, waypoints[0].longt, waypoints[0].time),
+            (waypoints[1].lat, waypoints[1].longt, waypoints[1].time)
+        );
+
+        // Without the last Exif segment there is nothing to find.
+        let bytes = fs::read("src/test_data/test20.jpg")?;
+        let exif = bytes.windows(6).rposition(|id| id == EXIF_ID).unwrap();
+        let cut = bytes[..exif - 4].to_vec();
+        let mut parsed = Vec::new();
+        let e = parse_stream(&mut Cursor::new(cut), "cut.jpg", &mut parsed, None).unwrap_err();
+        assert_eq!(e.to_string(), Skip::NoGps.to_string());
+        Ok(())
+    }
+
+    #[test]
+    fn test_date_taken() -> Result<()> {
+        // test21.jpg has the GPS time 23:30:00 but no datestamp, it was taken
+        // in Tokyo at 08:30 local time the next day.
+        let mut waypoints: Vec<GpsInfo> = Vec::new();
+
+        read_geotags("src/test_data/test21.jpg", &mut waypoints, None)?;
+        assert_eq!(
+            waypoints[0].time.map(format_time).unwrap(),
+            "2022-06-29T23:30:00Z"
+        );
+
+        let day = |text: &str, hms: u64| utc_day(text, hms).map(format_time);
+        let evening = 20 * 3600;
+        assert_eq!(
+            day("2022:06:30 13:00:00", evening).unwrap(),
+            "2022-06-30T00:00:00Z"
+        );
+        assert_eq!(
+            day("2022:12:31 22:00:00", 3 * 3600).unwrap(),
+            "2023-01-01T00:00:00Z"
+        );
+        assert_eq!(day("2022:02:30 13:00:00", evening), None);
+        assert_eq!(day("    :  :     :  :  ", evening), None);
+        assert_eq!(day("2022:06:30", evening), None);
+
+        // Without either date only the location is left.
+        let bytes = fs::read("src/test_data/test21.jpg")?;
+        let taken = bytes.windows(10).position(|d| d == b"2022:06:30").unwrap();
+        let mut undated = bytes.clone();
+        undated[taken..taken + 4].copy_from_slice(b"    ");
+        let mut parsed = Vec::new();
+        parse_stream(&mut Cursor::new(undated), "undated.jpg", &mut parsed, None)?;
+        assert_eq!((parsed[0].lat, parsed[0].time), (waypoints[0].lat, None));
+        Ok(())
+    }
+
+    #[test]
+    fn test_standalone_markers() -> Result<()> {
+        // test8.jpg is a progressive JPEG with test1.jpg's Exif segment,
+        // preceded by RST0, TEM, DRI and RST7 markers.
+        let mut waypoints: Vec<GpsInfo> = Vec::new();
+
+        read_geotags("src/test_data/test1.jpg", &mut waypoints, None)?;
+        read_geotags("src/test_data/test8.jpg", &mut waypoints, None)?;
+        assert_eq!(waypoints.len(), 2);
+        assert_eq!(waypoints[0].lat, waypoints[1].lat);
+        assert_eq!(waypoints[0].time, waypoints[1].time);
+        Ok(())
+    }
+
+    #[test]
+    fn test_gpx_1_0() -> Result<()> {
+        let mut waypoints: Vec<GpsInfo> = Vec::new();
+        let mut opts = OutputOpts::new("Test map");
+
+        read_geotags("src/test_data/test1.jpg", &mut waypoints, None)?;
+        read_geotags("src/test_data/test7.jpg", &mut waypoints, None)?;
+        waypoints[0].link = Some(("a.jpg".to_string(), "a".to_string()));
+        opts.gpx_version = GpxVersion::V1_0;
+
+        let mut buf: AV = AV::new();
+        print_xml(&mut waypoints, &mut buf, &opts)?;
+        let output = std::str::from_utf8(&buf).unwrap();
+        assert!(output.contains("version=\"1.0\""));
+        assert!(output.contains("<fix>dgps</fix></trkpt>"));
+        assert!(
+            output.contains("<course>41.00</course><url>a.jpg</url><urlname>a</urlname></trkpt>")
+        );
+        assert!(!output.contains("<extensions>"));
+
+        // Reading it back yields the same track in 1.1 format.
+        let mut parsed: Vec<GpsInfo> = Vec::new();
+        parse_gpx(output, "test.gpx", &mut parsed)?;
+        opts.gpx_version = GpxVersion::V1_1;
+        let mut expected: AV = AV::new();
+        let mut again: AV = AV::new();
+        print_xml(&mut waypoints, &mut expected, &opts)?;
+        print_xml(&mut parsed, &mut again, &opts)?;
+        assert_eq!(
+            std::str::from_utf8(&expected).unwrap(),
+            std::str::from_utf8(&again).unwrap()
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn test_template() -> Result<()> {
+        let mut waypoints: Vec<GpsInfo> = Vec::new();
+        let mut opts = OutputOpts::new("Test map");
+
+        read_geotags("src/test_data/test5.jpg", &mut waypoints, None)?;
+        read_geotags("src/test_data/test9.jpg", &mut waypoints, None)?;
+        opts.template = Some(parse_template(
+            "{{{lat}}},{lon},{time},{epoch}{alt},{file}",
+        )?);
+        opts.precision = 2;
+
+        let mut buf: AV = AV::new();
+        print_xml(&mut waypoints, &mut buf, &opts)?;
+        assert_eq!(
+            std::str::from_utf8(&buf).unwrap(),
+            "{35.69},139.69,2020-03-21T03:15:42Z,1584760542,src/test_data/test9.jpg\n\
+             {37.77},-122.42,,,src/test_data/test5.jpg\n"
+        );
+        assert!(parse_template("{lat},{speed}").is_err());
+        assert!(parse_template("{lat},{lon").is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn test_precision() -> Result<()> {
+        let mut opts = OutputOpts::new("Test map");
+        let mut output = |precision: usize| -> Result<String> {
+            let mut waypoints: Vec<GpsInfo> = Vec::new();
+            let mut buf: AV = AV::new();
+
+            for name in ["test0.jpg", "test1.jpg", "test3.jpg"] {
+                read_geotags(Path::new("src/test_data").join(name), &mut waypoints, None)?;
+            }
+            opts.template = Some(parse_template("{lat} {lon}")?);
+            opts.precision = precision;
+            print_xml(&mut waypoints, &mut buf, &opts)?;
+            Ok(String::from_utf8(buf).unwrap())
+        };
+
+        // The coordinates are kept as read and rounded to the nearest value
+        // when printed, 66.0457555... comes out as 66.04576, not truncated to
+        // 66.04575. result.txt holds the same rounded values.
+        assert_eq!(
+            output(5)?,
+            "-54.81007 -68.31601\n-33.85744 151.23981\n66.04576 -17.34345\n"
+        );
+        assert_eq!(
+            output(7)?,
+            "-54.8100750 -68.3160056\n-33.8574389 151.2398139\n66.0457556 -17.3434500\n"
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn test_dms() {
+        let w = GpsInfo {
+            lat: 37.774_9,
+            longt: -122.419_4,
+            ..GpsInfo::new()
+        };
+        let (d, m, s, h) = w.lat_dms();
+
+        assert_eq!((d, m, h), (37, 46, 'N'));
+        assert!((s - 29.64).abs() < 1e-6);
+        assert_eq!(format_dms(w.lat_dms()), "37°46'29.64\"N");
+        assert_eq!(format_dms(w.lon_dms()), "122°25'09.84\"W");
+        // Seconds rounding up to a full minute.
+        assert_eq!(format_dms((10, 59, 59.999, 'S')), "11°00'00.00\"S");
+    }
+
+    #[test]
+    fn test_to_ascii() {
+        assert_eq!(
+            to_ascii("<name>Zürich 東京</name>", true),
+            "<name>Z&#xFC;rich &#x6771;&#x4EAC;</name>"
+        );
+        assert_eq!(to_ascii("Zürich.jpg", false), "Z?rich.jpg");
+    }
+
+    #[test]
+    fn test_decimal_degrees() -> Result<()> {
+        // test12.jpg has coordinates as single rationals of decimal degrees.
+        let mut waypoints: Vec<GpsInfo> = Vec::new();
+
+        read_geotags("src/test_data/test5.jpg", &mut waypoints, None)?;
+        read_geotags("src/test_data/test12.jpg", &mut waypoints, None)?;
+        assert!((waypoints[0].lat - waypoints[1].lat).abs() < 1e-9);
+        assert!((waypoints[0].longt - waypoints[1].longt).abs() < 1e-9);
+        Ok(())
+    }
+
+    #[test]
+    fn test_gps_version() -> Result<()> {
+        let mut waypoints: Vec<GpsInfo> = Vec::new();
+
+        read_geotags("src/test_data/test0.jpg", &mut waypoints, None)?;
+        read_geotags("src/test_data/test1.jpg", &mut waypoints, None)?;
+        assert_eq!(waypoints[0].gps_version, Some([2, 2, 0, 0]));
+        assert_eq!(waypoints[1].gps_version, None);
+        Ok(())
+    }
+
+    #[test]
+    fn test_bad_count() {
+        // The GPSLatitude entry of test11.jpg has six values.
+        let mut waypoints: Vec<GpsInfo> = Vec::new();
+
+        let e = read_geotags("src/test_data/test11.jpg", &mut waypoints, None).unwrap_err();
+        assert_eq!(e.kind(), ErrorKind::Other);
+        assert_eq!(e.to_string(), "GPS entry 0x0002 has 6 value(s)");
+        assert!(waypoints.is_empty());
+    }
+
+    #[test]
+    fn test_output_format() -> Result<()> {
+        let mut opts = OutputOpts::new("Test map");
+
+        assert!(find_format("nmea").is_none());
+        for format in FORMATS.iter() {
+            let mut waypoints = vec![GpsInfo {
+                lat: 1.0,
+                time: Some(5),
+                ..GpsInfo::new()
+            }];
+            let mut buf: AV = AV::new();
+
+            opts.format = find_format(format.name()).unwrap();
+            assert_eq!(output_extension(&opts), Some(format.extension()));
+            print_xml(&mut waypoints, &mut buf, &opts)?;
+            assert!(!buf.is_empty());
+        }
+
+        // A format of the library user, going by the public accessors only.
+        struct Lines;
+
+        impl OutputFormat for Lines {
+            fn name(&self) -> &'static str {
+                "lines"
+            }
+
+            fn extension(&self) -> &'static str {
+                "txt"
+            }
+
+            fn is_xml(&self) -> bool {
+                false
+            }
+
+            fn write(
+                &self,
+                av: &mut dyn Write,
+                track: &[GpsInfo],
+                opts: &OutputOpts,
+            ) -> Result<()> {
+                writeln!(av, "{}", opts.map_name())?;
+                for w in track {
+                    writeln!(
+                        av,
+                        "{:.*} {:.*}",
+                        opts.precision(),
+                        w.lat(),
+                        opts.precision(),
+                        w.lon()
+                    )?;
+                }
+                Ok(())
+            }
+        }
+
+        assert!(find_format("lines").is_none());
+        register_format(&Lines);
+        opts.format = find_format("lines").unwrap();
+        let mut waypoints: Vec<GpsInfo> = [(1.0, 5), (1.0, 5), (2.5, 6)]
+            .iter()
+            .map(|&(lat, time)| GpsInfo {
+                lat,
+                time: Some(time),
+                ..GpsInfo::new()
+            })
+            .collect();
+        let mut buf: AV = AV::new();
+        opts.quiet = true;
+        print_xml(&mut waypoints, &mut buf, &opts)?;
+        // Sorted and deduplicated, like for the built-in formats.
+        assert_eq!(
+            String::from_utf8(buf).unwrap(),
+            "Test map\n1.00000 0.00000\n2.50000 0.00000\n"
+        );
+        assert_eq!(
+            write_map(&Lines, &waypoints[..1], "Other")?,
+            b"Other\n1.00000 0.00000\n"
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn test_large_output() -> Result<()> {
+        let n: u64 = 20_000;
+        let mut waypoints: Vec<GpsInfo> = (0..n)
+            .map(|i| GpsInfo {
+                lat: 10.0 + i as f64 * 0.001,
+                longt: 20.0,
+                alt: Some(i as f64),
+                time: Some(1_700_000_000 + i),
+                ..GpsInfo::new()
+            })
+            .collect();
+        let mut buf: AV = AV::new();
+        let mut opts = OutputOpts::new("Test map");
+
+        opts.quiet = true;
+        print_xml(&mut waypoints, &mut buf, &opts)?;
+        assert!(buf.len() > 1_000_000);
+
+        let mut parsed: Vec<GpsInfo> = Vec::new();
+        parse_gpx(std::str::from_utf8(&buf).unwrap(), "test.gpx", &mut parsed)?;
+        assert_eq!(parsed.len(), n as usize);
+        assert_eq!(
+            parsed.last().map(|w| w.time),
+            Some(Some(1_700_000_000 + n - 1))
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn test_flatten() -> Result<()> {
+        let mut opts = OutputOpts::new("Test map");
+        // About 1.1 m apart, out of chronological order, two sharing the
+        // time.
+        let mut waypoints: Vec<GpsInfo> = [3, 1, 4, 1, 5, 9, 2, 6]
+            .iter()
+            .enumerate()
+            .map(|(i, &time)| GpsInfo {
+                file_name: format!("{}.jpg", i),
+                lat: 10.0 + i as f64 * 0.00001,
+                time: Some(time),
+                ..GpsInfo::new()
+            })
+            .collect();
+        let mut buf: AV = AV::new();
+
+        opts.flatten = true;
+        opts.max_points = Some(2);
+        opts.template = Some(parse_template("{file}")?);
+        print_xml(&mut waypoints, &mut buf, &opts)?;
+        assert_eq!(
+            std::str::from_utf8(&buf).unwrap(),
+            "0.jpg\n1.jpg\n2.jpg\n3.jpg\n4.jpg\n5.jpg\n6.jpg\n7.jpg\n"
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn test_min_distance() -> Result<()> {
+        let mut opts = OutputOpts::new("Test map");
+        // About 1.1 m apart along the meridian.
+        let track = || -> Vec<GpsInfo> {
+            (0..10)
+                .map(|i| GpsInfo {
+                    lat: 10.0 + i as f64 * 0.00001,
+                    time: Some(i),
+                    ..GpsInfo::new()
+                })
+                .collect()
+        };
+
+        // The default thins out to one waypoint per 5 m.
+        let mut waypoints = track();
+        let mut buf: AV = AV::new();
+        print_xml(&mut waypoints, &mut buf, &opts)?;
+        assert_eq!(
+            std::str::from_utf8(&buf).unwrap().matches("<trkpt").count(),
+            2
+        );
+
+        for (meters, expected) in [(0.0, 10), (0.5, 10), (2.0, 5), (5.0, 2), (50.0, 1)].iter() {
+            let mut waypoints = track();
+            let mut buf: AV = AV::new();
+
+            opts.min_distance = *meters;
+            print_xml(&mut waypoints, &mut buf, &opts)?;
+            let text = std::str::from_utf8(&buf).unwrap();
+            assert_eq!(text.matches("<trkpt").count(), *expected, "{}", meters);
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn test_thin_out_fast() {
+        let opts = OutputOpts::new("Test map");
+        // Irregular steps of up to about 3.5 m in all directions, at various
+        // latitudes and across the antimeridian.
+        let mut waypoints: Vec<GpsInfo> = Vec::new();
+        for (lat, longt) in [
+            (0.0, 0.0),
+            (48.85, 2.35),
+            (-33.86, 151.21),
+            (64.1, 179.99995),
+        ]
+        .iter()
+        {
+            for i in 0..50u64 {
+                let step = (i * 7 % 11) as f64 * 0.000_003;
+                waypoints.push(GpsInfo {
+                    lat: lat + step * (i % 3) as f64,
+                    longt: wrap_longitude(longt + step * (i % 4) as f64),
+                    time: Some(i),
+                    ..GpsInfo::new()
+                });
+            }
+        }
+        // The same as thin_out(), with the haversine distance.
+        let mut exact: Vec<bool> = Vec::new();
+        let mut last: Option<&GpsInfo> = None;
+        for w in &waypoints {
+            let close = last.is_some_and(|last| w.distance_from(last) <= opts.min_distance);
+            if !close {
+                last = Some(w);
+            }
+            exact.push(!close);
+        }
+
+        let keep = thin_out(&waypoints, &opts);
+        assert_eq!(keep, exact);
+        assert!(keep.iter().any(|k| !k));
+        assert!(keep.iter().filter(|&&k| k).count() > 4);
+    }
+
+    #[test]
+    fn test_gap_seconds() -> Result<()> {
+        let mut opts = OutputOpts::new("Test map");
+
+        for (gap, segments) in [(None, 1), (Some(100), 2), (Some(1000), 1), (Some(5), 3)].iter() {
+            let mut waypoints: Vec<GpsInfo> = [Some(0), Some(10), None, Some(1000)]
+                .iter()
+                .enumerate()
+                .map(|(i, &time)| GpsInfo {
+                    lat: i as f64,
+                    time,
+                    ..GpsInfo::new()
+                })
+                .collect();
+            let mut buf: AV = AV::new();
+
+            opts.gap_seconds = *gap;
+            print_xml(&mut waypoints, &mut buf, &opts)?;
+            let gpx = std::str::from_utf8(&buf).unwrap();
+            assert_eq!(gpx.matches("<trkseg>").count(), *segments, "{:?}", gap);
+            assert_eq!(gpx.matches("</trkseg>").count(), *segments, "{:?}", gap);
+            assert_eq!(gpx.matches("<trk>").count(), 1);
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn test_csv_json() -> Result<()> {
+        let mut opts = OutputOpts::new("Test map");
+        let track = || {
+            vec![
+                GpsInfo {
+                    lat: 1.5,
+                    longt: -2.25,
+                    alt: Some(10.0),
+                    time: Some(1_709_209_800),
+                    ..GpsInfo::new()
+                },
+                GpsInfo {
+                    lat: -3.0,
+                    longt: 4.0,
+                    ..GpsInfo::new()
+                },
+            ]
+        };
+
+        for (name, expected) in [
+            (
+                "csv",
+                "lat,lon,alt,time\n\
+                 1.50000,-2.25000,10.0,2024-02-29T12:30:00Z\n\
+                 -3.00000,4.00000,,\n",
+            ),
+            (
+                "json",
+                "[\n\
+                 {\"lat\": 1.50000, \"lon\": -2.25000, \"alt\": 10.0, \
+                 \"time\": \"2024-02-29T12:30:00Z\"},\n\
+                 {\"lat\": -3.00000, \"lon\": 4.00000, \"alt\": null, \"time\": null}\n\
+                 ]\n",
+            ),
+        ] {
+            let mut waypoints = track();
+            let mut buf: AV = AV::new();
+
+            opts.format = find_format(name).unwrap();
+            print_xml(&mut waypoints, &mut buf, &opts)?;
+            assert_eq!(std::str::from_utf8(&buf).unwrap(), expected);
+            // Neither is laid out as XML.
+            opts.layout = Layout::Pretty;
+            assert_eq!(
+                finish_output(&buf, &opts, opts.format.is_xml()),
+                expected.as_bytes()
+            );
+            opts.layout = Layout::Lines;
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn test_print_kml() -> Result<()> {
+        let mut opts = OutputOpts::new("Test map");
+        let mut waypoints: Vec<GpsInfo> = [
+            ("a/1.jpg", 10.0, 20.0, Some(30.0), 1),
+            ("a/1.jpg", 10.0, 20.0, None, 1),
+            ("a/2.jpg", 11.0, -21.0, None, 2),
+            ("b/3.jpg", -12.0, 22.5, Some(-1.0), 3),
+        ]
+        .iter()
+        .map(|&(name, lat, longt, alt, time)| GpsInfo {
+            file_name: name.to_string(),
+            lat,
+            longt,
+            alt,
+            time: Some(time),
+            ..GpsInfo::new()
+        })
+        .collect();
+        let mut buf: AV = AV::new();
+
+        opts.format = find_format("kml").unwrap();
+        opts.track_per = TrackPer::Dir;
+        print_xml(&mut waypoints, &mut buf, &opts)?;
+        let kml = std::str::from_utf8(&buf).unwrap();
+        // The duplicate is dropped, longitude goes first.
+        assert!(kml.contains(
+            "<name>a</name>\n<LineString>\n\
+             <coordinates>20.00000,10.00000,30.0 -21.00000,11.00000</coordinates>\n\
+             </LineString>\n"
+        ));
+        assert!(kml.contains(
+            "<name>b</name>\n<Point>\n<coordinates>22.50000,-12.00000,-1.0</coordinates>\n"
+        ));
+        assert_eq!(kml.matches("<Placemark>").count(), 2);
+        assert!(kml.ends_with("</Document>\n</kml>\n"));
+        assert!(!kml.contains("Style"));
+
+        // The style is shared by the placemarks, the color goes aabbggrr.
+        assert_eq!(kml_color("FF8000").as_deref(), Some("ff0080ff"));
+        assert_eq!(kml_color("FF80"), None);
+        assert_eq!(kml_color("GG8000"), None);
+        opts.kml_color = kml_color("ff8000");
+        opts.kml_width = Some(4.5);
+        buf.clear();
+        print_xml(&mut waypoints, &mut buf, &opts)?;
+        let kml = std::str::from_utf8(&buf).unwrap();
+        assert!(kml.contains(
+            "<Style id=\"track\"><LineStyle><color>ff0080ff</color>\
+             <width>4.5</width></LineStyle></Style>\n"
+        ));
+        assert_eq!(kml.matches("<styleUrl>#track</styleUrl>").count(), 2);
+
+        // Other formats do not care.
+        opts.format = find_format("gpx").unwrap();
+        buf.clear();
+        print_xml(&mut waypoints, &mut buf, &opts)?;
+        assert!(!std::str::from_utf8(&buf).unwrap().contains("ff0080ff"));
+        Ok(())
+    }
+
+    #[test]
+    fn test_check_extension() {
+        let check = |name: &str, fix| check_extension(PathBuf::from(name), "gpx", fix);
+
+        assert_eq!(check("trip.gpx", true), Path::new("trip.gpx"));
+        assert_eq!(check("trip.GPX", true), Path::new("trip.GPX"));
+        assert_eq!(check("trip", true), Path::new("trip.gpx"));
+        assert_eq!(check("trip.xml", true), Path::new("trip.xml.gpx"));
+        assert_eq!(check("trip", false), Path::new("trip"));
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_non_utf8_args() {
+        use std::os::unix::ffi::OsStringExt;
+
+        let raw = OsString::from_vec(b"x/\xff.jpg".to_vec());
+        let os_args = vec![OsString::from("exifgeo"), OsString::from("-o"), raw.clone()];
+        let args = utf8_args(&os_args);
+
+        assert_eq!(args[..2], ["exifgeo", "-o"]);
+        assert_eq!(arg_path(&args[2], &os_args), PathBuf::from(raw));
+        assert_eq!(arg_path(&args[1], &os_args), Path::new("-o"));
+    }
+
+    #[test]
+    fn test_map_format() {
+        let o = prepare_opts();
+        let env_format = || Some("kml".to_string());
+        let flag = o.parse(["--format", "csv"]).unwrap();
+        let none = o.parse(Vec::<String>::new()).unwrap();
+
+        assert_eq!(map_format(&flag, env_format()).as_deref(), Some("csv"));
+        assert_eq!(map_format(&none, env_format()).as_deref(), Some("kml"));
+        assert_eq!(map_format(&none, Some(String::new())), None);
+        assert_eq!(map_format(&none, None), None);
+    }
+
+    #[test]
+    fn test_no_clobber() -> Result<()> {
+        let path = env::temp_dir().join(format!("exifgeo-{}.gpx", std::process::id()));
+        let mut opts = OutputOpts::new("Test map");
+
+        opts.no_clobber = true;
+        write_output(&path, b"first", &opts)?;
+        let e = write_output(&path, b"second", &opts).unwrap_err();
+        assert_eq!(e.kind(), ErrorKind::AlreadyExists);
+        assert_eq!(fs::read(&path)?, b"first");
+        opts.no_clobber = false;
+        write_output(&path, b"third", &opts)?;
+        assert_eq!(fs::read(&path)?, b"third");
+
+        // No temporary files are left behind.
+        let dir = path.parent().unwrap();
+        let stem = path.file_name().unwrap().to_string_lossy().to_string();
+        for entry in fs::read_dir(dir)? {
+            let name = entry?.file_name().to_string_lossy().to_string();
+            assert!(name == stem || !name.starts_with(&stem));
+        }
+        fs::remove_file(&path)
+    }
+
+    #[test]
+    fn test_expand_inputs() -> Result<()> {
+        let root = env::temp_dir().join(format!("exifgeo-walk-{}", std::process::id()));
+        let sub = root.join("b");
+        fs::create_dir_all(&sub)?;
+        for name in ["c.JPG", "a.jpeg", "notes.txt", "b/d.jpg"] {
+            fs::write(root.join(name), b"")?;
+        }
+        let name = |p: &str| root.join(p);
+        let inputs = [root.clone(), "-".into(), "notes.txt".into()];
+
+        // Files given are kept whatever their names.
+        let files = expand_inputs(&inputs, false, false);
+        assert_eq!(
+            files,
+            [
+                name("a.jpeg"),
+                name("c.JPG"),
+                "-".into(),
+                "notes.txt".into()
+            ]
+        );
+        let files = expand_inputs(&inputs[..1], true, false);
+        assert_eq!(files, [name("a.jpeg"), name("b/d.jpg"), name("c.JPG")]);
+
+        // A link back to the top is not followed.
+        #[cfg(unix)]
+        {
+            std::os::unix::fs::symlink(&root, sub.join("loop"))?;
+            let files = expand_inputs(&inputs[..1], true, false);
+            assert_eq!(files, [name("a.jpeg"), name("b/d.jpg"), name("c.JPG")]);
+
+            // Names which are not UTF-8 are opened as they are, and only
+            // show up lossily converted in the output.
+            use std::os::unix::ffi::OsStrExt;
+            let dir = root.join("x");
+            fs::create_dir(&dir)?;
+            let photo = dir.join(std::ffi::OsStr::from_bytes(b"\xff.jpg"));
+            fs::copy("src/test_data/test1.jpg", &photo)?;
+            let files = expand_inputs(&[dir], false, false);
+            assert_eq!(files, [photo]);
+            let waypoints = parse_files(&files, true, None, None)?;
+            assert!(waypoints[0].file_name.ends_with("x/\u{fffd}.jpg"));
+        }
+        fs::remove_dir_all(&root)
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_follow_symlinks() -> Result<()> {
+        use std::os::unix::fs::symlink;
+
+        let root = env::temp_dir().join(format!("exifgeo-links-{}", std::process::id()));
+        let trip = root.join("trip");
+        let other = root.join("other");
+        fs::create_dir_all(trip.join("day1"))?;
+        fs::create_dir_all(&other)?;
+        fs::write(trip.join("a.jpg"), b"")?;
+        fs::write(trip.join("day1/b.jpg"), b"")?;
+        fs::write(other.join("c.jpg"), b"")?;
+        // A linked photo folder, and links back up the tree and to itself.
+        symlink(&other, trip.join("linked"))?;
+        symlink(&trip, trip.join("day1/up"))?;
+        symlink(&root, other.join("root"))?;
+        let inputs = [trip.clone()];
+
+        let files = expand_inputs(&inputs, true, false);
+        assert_eq!(files, [trip.join("a.jpg"), trip.join("day1/b.jpg")]);
+        // Each directory is walked once, under the first name it is met by.
+        let files = expand_inputs(&inputs, true, true);
+        assert_eq!(
+            files,
+            [
+                trip.join("a.jpg"),
+                trip.join("day1/b.jpg"),
+                trip.join("linked/c.jpg")
+            ]
+        );
+        // Without recursion the linked directories are not walked either.
+        let files = expand_inputs(&inputs, false, true);
+        assert_eq!(files, [trip.join("a.jpg")]);
+        fs::remove_dir_all(&root)
+    }
+
+    #[test]
+    fn test_track_per_dir() -> Result<()> {
+        let mut waypoints: Vec<GpsInfo> = Vec::new();
+        let mut opts = OutputOpts::new("Test map");
+
+        for (i, name) in ["b/1.jpg", "a/2.jpg", "b/3.jpg", "a/4.jpg"]
+            .iter()
+            .enumerate()
+        {
+            waypoints.push(GpsInfo {
+                file_name: name.to_string(),
+                lat: i as f64,
+                time: Some(i as u64),
+                ..GpsInfo::new()
+            });
+        }
+        opts.track_per = TrackPer::Dir;
+
+        let mut buf: AV = AV::new();
+        print_xml(&mut waypoints, &mut buf, &opts)?;
+        let output = std::str::from_utf8(&buf).unwrap();
+        let a = output.find("<name>a</name><number>1</number>").unwrap();
+        let b = output.find("<name>b</name><number>2</number>").unwrap();
+        assert!(a < b);
+        assert_eq!(output.matches("<trk>").count(), 2);
+        assert_eq!(output.matches("<trkpt").count(), 4);
+        // No extensions, no namespace declarations.
+        assert!(!output.contains("xmlns"));
+        Ok(())
+    }
+
+    #[test]
+    fn test_destination() -> Result<()> {
+        // test15.jpg has destination coordinates and big endian UCS-2 area
+        // information, test1.jpg has neither.
+        let mut waypoints: Vec<GpsInfo> = Vec::new();
+
+        read_geotags("src/test_data/test15.jpg", &mut waypoints, None)?;
+        read_geotags("src/test_data/test1.jpg", &mut waypoints, None)?;
+        let (lat, longt) = waypoints[0].dest.unwrap();
+        assert!((lat + 33.8568).abs() < 1e-4);
+        assert!((longt - 151.2153).abs() < 1e-4);
+        assert_eq!(waypoints[0].area.as_deref(), Some("Sydney Opera House"));
+        assert!(waypoints[1].dest.is_none());
+        assert!(waypoints[1].area.is_none());
+        Ok(())
+    }
+
+    #[test]
+    fn test_force_format() -> Result<()> {
+        // test16.tif is a bare TIFF file, which can not be read as JPEG.
+        let mut waypoints: Vec<GpsInfo> = Vec::new();
+        let name = "src/test_data/test16.tif";
+
+        let e = read_geotags(name, &mut waypoints, Some(Format::Jpeg)).unwrap_err();
+        assert_eq!(e.kind(), ErrorKind::Other);
+        read_geotags(name, &mut waypoints, Some(Format::Tiff))?;
+        assert_eq!(waypoints.len(), 1);
+        assert!((waypoints[0].lat - 48.8582).abs() < 1e-4);
+        Ok(())
+    }
+
+    #[test]
+    fn test_detect_format() -> Result<()> {
+        let mut waypoints: Vec<GpsInfo> = Vec::new();
+
+        for name in ["test1.jpg", "test16.tif", "test17.mov", "test18.tif"].iter() {
+            read_geotags(format!("src/test_data/{}", name), &mut waypoints, None)?;
+        }
+        assert_eq!(waypoints.len(), 4);
+
+        for magic in [&b"\x89PNG\r\n\x1a\n"[..], b"RIFF\0\0\0\0WEBP", b"II"].iter() {
+            let e =
+                parse_stream(&mut Cursor::new(magic), "magic", &mut waypoints, None).unwrap_err();
+            assert_eq!(e.to_string(), Skip::NotImage.to_string());
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn test_merge_tolerance() -> Result<()> {
+        // result.txt is the map of test0.jpg to test3.jpg, merging it back
+        // adds nothing new.
+        let mut waypoints: Vec<GpsInfo> = Vec::new();
+        let mut opts = OutputOpts::new("Test map");
+
+        for i in 0..4 {
+            read_geotags(format!("src/test_data/test{}.jpg", i), &mut waypoints, None)?;
+        }
+        opts.quiet = true;
+        opts.merge_tolerance = Some(1.0);
+        read_gpx(Path::new("src/test_data/result.txt"), &mut waypoints, &opts)?;
+        assert_eq!(waypoints.len(), 4);
+
+        // Without the tolerance everything is merged, unless the timestamps
+        // match.
+        opts.merge_tolerance = None;
+        read_gpx(Path::new("src/test_data/result.txt"), &mut waypoints, &opts)?;
+        assert_eq!(waypoints.len(), 8);
+        Ok(())
+    }
+
+    #[test]
+    fn test_big_tiff() -> Result<()> {
+        // test18.tif is big endian BigTIFF, its DOP is kept in the entry.
+        let mut waypoints: Vec<GpsInfo> = Vec::new();
+
+        read_geotags("src/test_data/test18.tif", &mut waypoints, None)?;
+        assert!((waypoints[0].lat - 64.1466).abs() < 1e-4);
+        assert!((waypoints[0].longt + 21.9426).abs() < 1e-4);
+        assert_eq!(
+            format_time(waypoints[0].time.unwrap()),
+            "2023-07-04T14:30:15Z"
+        );
+        assert_eq!(waypoints[0].dop, Some(2.5));
+        Ok(())
+    }
+
+    #[test]
+    fn test_coalesce_time() -> Result<()> {
+        let mut waypoints: Vec<GpsInfo> = Vec::new();
+        let mut opts = OutputOpts::new("Test map");
+
+        for (lat, longt, time) in [
+            (10.0, 179.9, 5),
+            (11.0, -179.9, 5),
+            (20.0, 0.0, 6),
+            (12.0, 179.9, 5),
+        ]
+        .iter()
+        {
+            waypoints.push(GpsInfo {
+                lat: *lat,
+                longt: *longt,
+                time: Some(*time),
+                ..GpsInfo::new()
+            });
+        }
+        opts.coalesce_time = true;
+
+        let mut buf: AV = AV::new();
+        print_xml(&mut waypoints, &mut buf, &opts)?;
+        let output = std::str::from_utf8(&buf).unwrap();
+        assert_eq!(output.matches("<trkpt").count(), 2);
+        assert!(output.contains("<trkpt lat=\"11.00000\" lon=\"179.96667\">"));
+        assert!(output.contains("<trkpt lat=\"20.00000\" lon=\"0.00000\">"));
+        Ok(())
+    }
+
+    #[test]
+    fn test_one_per_file() -> Result<()> {
+        let mut opts = OutputOpts::new("Test map");
+
+        opts.precision = 0;
+        opts.template = Some(parse_template("{lat}")?);
+        for (pick, expected) in [
+            (PerFile::First, "3\n5\n"),
+            (PerFile::Last, "5\n4\n"),
+            (PerFile::Median, "5\n2\n"),
+        ]
+        .iter()
+        {
+            let mut waypoints: Vec<GpsInfo> = Vec::new();
+            let mut buf: AV = AV::new();
+
+            for (name, lat, time) in [
+                ("a.jpg", 1.0, 30),
+                ("b.jpg", 5.0, 15),
+                ("a.jpg", 2.0, 20),
+                ("a.jpg", 3.0, 10),
+                ("a.jpg", 4.0, 40),
+            ]
+            .iter()
+            {
+                waypoints.push(GpsInfo {
+                    file_name: name.to_string(),
+                    lat: *lat,
+                    time: Some(*time),
+                    ..GpsInfo::new()
+                });
+            }
+            opts.one_per_file = Some(*pick);
+            print_xml(&mut waypoints, &mut buf, &opts)?;
+            assert_eq!(std::str::from_utf8(&buf).unwrap(), *expected);
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn test_max_points() -> Result<()> {
+        // A zig-zag with big turns at 10 and 20 and small ones elsewhere, and a
+        // duplicate of the first point.
+        let mut opts = OutputOpts::new("Test map");
+        let mut waypoints: Vec<GpsInfo> = Vec::new();
+
+        for i in 0..30u64 {
+            let peak = match i {
+                10 => 0.01,
+                20 => -0.01,
+                _ => 0.0001 * (i % 2) as f64,
+            };
+            waypoints.push(GpsInfo {
+                lat: 45.0 + peak,
+                longt: 7.0 + 0.001 * i as f64,
+                time: Some(100 + i),
+                ..GpsInfo::new()
+            });
+        }
+        waypoints.push(GpsInfo {
+            lat: 46.0,
+            longt: 7.0,
+            time: Some(100),
+            ..GpsInfo::new()
+        });
+        opts.template = Some(parse_template("{epoch}")?);
+        opts.max_points = Some(4);
+
+        let mut buf: AV = AV::new();
+        print_xml(&mut waypoints, &mut buf, &opts)?;
+        assert_eq!(std::str::from_utf8(&buf).unwrap().lines().count(), 4);
+        assert_eq!(waypoints.len(), 4);
+        assert!((waypoints[0].lat - 45.0).abs() < 1e-9);
+        assert_eq!(waypoints[1].time, Some(110));
+        assert_eq!(waypoints[2].time, Some(120));
+        assert_eq!(waypoints[3].time, Some(129));
+
+        // The small turns are about 11 meters off the straight line, the
+        // spike at 10 is kept with its feet.
+        let track: Vec<GpsInfo> = (0..30u64)
+            .map(|i| GpsInfo {
+                lat: 45.0
+                    + if i == 10 {
+                        0.01
+                    } else {
+                        0.0001 * (i % 2) as f64
+                    },
+                longt: 7.0 + 0.001 * i as f64,
+                ..GpsInfo::new()
+            })
+            .collect();
+        let kept = |keep: Vec<bool>| keep.iter().filter(|&&k| k).count();
+        assert_eq!(kept(simplify(&track, usize::MAX, Some(100.0))), 5);
+        assert_eq!(kept(simplify(&track, usize::MAX, Some(1.0))), 30);
+        assert_eq!(kept(simplify(&track, 4, Some(1.0))), 4);
+
+        let a = GpsInfo {
+            lat: 45.0,
+            longt: 7.0,
+            ..GpsInfo::new()
+        };
+        let b = GpsInfo {
+            lat: 45.0,
+            longt: 7.01,
+            ..GpsInfo::new()
+        };
+        let c = GpsInfo {
+            lat: 45.001,
+            longt: 7.005,
+            ..GpsInfo::new()
+        };
+        assert!((segment_distance(&c, &a, &b) - 111.2).abs() < 1.0);
+        assert!((segment_distance(&a, &c, &c) - a.distance_from(&c)).abs() < 1e-6);
+        Ok(())
+    }
+
+    #[test]
+    fn test_smooth() -> Result<()> {
+        let mut waypoints: Vec<GpsInfo> = Vec::new();
+        let mut opts = OutputOpts::new("Test map");
+
+        for (lat, h_error, time) in [(10.0, 1.0, 1), (13.0, 10.0, 2), (12.0, 1.0, 3)].iter() {
+            waypoints.push(GpsInfo {
+                lat: *lat,
+                longt: 179.9,
+                time: Some(*time),
+                h_error: Some(*h_error),
+                ..GpsInfo::new()
+            });
+        }
+        opts.smooth = Some(3);
+
+        let mut buf: AV = AV::new();
+        print_xml(&mut waypoints, &mut buf, &opts)?;
+        let output = std::str::from_utf8(&buf).unwrap();
+        assert!(output.contains("<trkpt lat=\"10.02970\" lon=\"179.90000\">"));
+        assert!(output.contains("<trkpt lat=\"11.00995\" lon=\"179.90000\">"));
+        assert!(output.contains("<trkpt lat=\"12.00990\" lon=\"179.90000\">"));
+        Ok(())
+    }
+
+    #[test]
+    fn test_parse_movie() -> Result<()> {
+        // test17.mov has the location in moov/udta/©xyz, after the media
+        // data.
+        let mut waypoints: Vec<GpsInfo> = Vec::new();
+
+        read_geotags("src/test_data/test17.mov", &mut waypoints, None)?;
+        assert_eq!(waypoints.len(), 1);
+        assert!((waypoints[0].lat - 37.7749).abs() < 1e-9);
+        assert!((waypoints[0].longt + 122.4194).abs() < 1e-9);
+        assert_eq!(waypoints[0].alt, Some(10.0));
+        assert_eq!(
+            format_time(waypoints[0].time.unwrap()),
+            "2023-07-04T10:20:30Z"
+        );
+
+        // Hostile nesting skips the file rather than overflowing the stack.
+        let levels = 300_000u32;
+        let mut bytes = b"\0\0\0\x10ftypqt  \0\0\0\0".to_vec();
+        for level in (0..levels).rev() {
+            bytes.extend_from_slice(&(8 * (level + 1)).to_be_bytes());
+            bytes.extend_from_slice(b"moov");
+        }
+        let e = parse_bytes(&bytes, "nested.mov", None).err().unwrap();
+        assert_eq!(e.kind(), ErrorKind::Other);
+        assert!(e.to_string().contains("nested too deep"));
+        Ok(())
+    }
+
+    #[test]
+    fn test_iso6709() {
+        let parse = quicktime::parse_iso6709;
+
+        assert_eq!(
+            parse("+37.7749-122.4194/"),
+            Some((37.7749, -122.4194, None))
+        );
+        assert_eq!(
+            parse("-3345.5+15112.5+5/"),
+            Some((-33.758_333_333_333_33, 151.208_333_333_333_34, Some(5.0)))
+        );
+        let (lat, longt, _) = parse("+402946.8-0795542.0CRSWGS_84/").unwrap();
+        assert!((lat - (40.0 + 29.0 / 60.0 + 46.8 / 3600.0)).abs() < 1e-9);
+        assert!((longt + (79.0 + 55.0 / 60.0 + 42.0 / 3600.0)).abs() < 1e-9);
+        assert_eq!(parse("+95.0+010.0/"), None);
+        assert_eq!(parse("+3760.0+12200.0/"), None);
+        assert_eq!(parse("37.7749-122.4194/"), None);
+        assert_eq!(parse("+37.7749/"), None);
+    }
+
+    #[test]
+    fn test_show_dropped() -> Result<()> {
+        let mut opts = OutputOpts::new("Test map");
+        // About 1.1 m apart along the meridian, then two far apart photos
+        // sharing the time.
+        let track = || -> Vec<GpsInfo> {
+            (0..12)
+                .map(|i| GpsInfo {
+                    file_name: format!("{}.jpg", i),
+                    lat: if i < 10 {
+                        10.0 + i as f64 * 0.00001
+                    } else {
+                        i as f64
+                    },
+                    time: Some(i.min(10)),
+                    ..GpsInfo::new()
+                })
+                .collect()
+        };
+        let output = |opts: &OutputOpts| -> Result<String> {
+            let mut buf: AV = AV::new();
+            print_xml(&mut track(), &mut buf, opts)?;
+            Ok(String::from_utf8(buf).unwrap())
+        };
+
+        opts.template = Some(parse_template("{file} ")?);
+        assert_eq!(output(&opts)?, "0.jpg \n5.jpg \n10.jpg \n");
+
+        // The rest of the close ones and the duplicate.
+        opts.show_dropped = true;
+        assert_eq!(
+            output(&opts)?.replace('\n', ""),
+            "1.jpg 2.jpg 3.jpg 4.jpg 6.jpg 7.jpg 8.jpg 9.jpg 11.jpg "
+        );
+
+        // Without thinning only the duplicate is dropped.
+        opts.min_distance = 0.0;
+        assert_eq!(output(&opts)?, "11.jpg \n");
+        Ok(())
+    }
+
+    #[test]
+    fn test_ifd_loop() {
+        // IFD0 of test13.jpg has no GPS pointer and points to itself as the
+        // next IFD.
+        let mut waypoints: Vec<GpsInfo> = Vec::new();
+
+        let e = read_geotags("src/test_data/test13.jpg", &mut waypoints, None).unwrap_err();
+        assert_eq!(e.to_string(), Skip::NoGps.to_string());
+    }
+
+    #[cfg(feature = "gzip")]
+    #[test]
+    fn test_gzip() {
+        assert_eq!(gzip::crc32(b"123456789"), 0xcbf4_3926);
+
+        // The GPX text compresses well, the trailer holds its CRC and size.
+        let text = fs::read("src/test_data/result.txt").expect("Failed to read result.txt");
+        let compressed = gzip::compress(&text);
+        let trailer = &compressed[compressed.len() - 8..];
+        assert_eq!(compressed[..3], [0x1f, 0x8b, 8]);
+        assert_eq!(trailer[..4], gzip::crc32(&text).to_le_bytes());
+        assert_eq!(trailer[4..], (text.len() as u32).to_le_bytes());
+        assert!(compressed.len() < text.len() * 2 / 3);
+
+        // Read back whole, anything damaged is refused.
+        assert_eq!(gzip::decompress(&compressed).unwrap(), text);
+        assert_eq!(gzip::decompress(&gzip::compress(b"")).unwrap(), b"");
+        let mut damaged = compressed.clone();
+        damaged[compressed.len() / 2] ^= 0x10;
+        assert!(gzip::decompress(&damaged).is_err());
+        assert!(gzip::decompress(&compressed[..compressed.len() - 1]).is_err());
+        assert!(gzip::decompress(&text).is_err());
+
+        // Optional header fields are skipped.
+        let mut named = compressed[..10].to_vec();
+        named[3] = 0x08;
+        named.extend_from_slice(b"result.txt\0");
+        named.extend_from_slice(&compressed[10..]);
+        assert_eq!(gzip::decompress(&named).unwrap(), text);
+    }
+
+    #[cfg(feature = "zip")]
+    #[test]
+    fn test_parse_zip() -> Result<()> {
+        // photos.zip holds test1.jpg deflated, test2.jpg stored, test3.jpg
+        // deflated in a subdirectory and a text file.
+        let mut expected: Vec<GpsInfo> = Vec::new();
+        let mut waypoints: Vec<GpsInfo> = Vec::new();
+
+        for i in 1..4 {
+            read_geotags(format!("src/test_data/test{}.jpg", i), &mut expected, None)?;
+        }
+        parse_zip(Path::new("src/test_data/photos.zip"), &mut waypoints, true)?;
+        assert_eq!(waypoints.len(), 3);
+        assert_eq!(
+            waypoints[2].file_name,
+            "src/test_data/photos.zip/trip/test3.jpg"
+        );
+        for (w, e) in waypoints.iter().zip(expected.iter()) {
+            assert_eq!((w.lat, w.longt, w.time), (e.lat, e.longt, e.time));
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn test_summary_span() {
+        let mut summary = Summary::default();
+        let a = GpsInfo {
+            time: Some(100_000),
+            ..GpsInfo::new()
+        };
+        let b = GpsInfo {
+            time: Some(100_000 + 26 * 3600 + 5 * 60 + 7),
+            ..GpsInfo::new()
+        };
+        let c = GpsInfo::new();
+
+        summary.add(&c);
+        assert_eq!(summary.span(), "unknown");
+        summary.add(&b);
+        summary.add(&a);
+        assert_eq!(summary.span(), "26:05:07");
+    }
+
+    #[test]
+    fn test_summary_gain() {
+        let mut summary = Summary::default();
+        let track: Vec<GpsInfo> = [Some(10.0), Some(25.0), None, Some(20.0), Some(32.5)]
+            .iter()
+            .map(|&alt| GpsInfo {
+                alt,
+                ..GpsInfo::new()
+            })
+            .collect();
+
+        for w in &track {
+            summary.add(w);
+        }
+        assert_eq!(summary.gain, 27.5);
+    }
+
+    #[test]
+    fn test_time_round_trip() -> Result<()> {
+        // GPS date and time as found in the GPS IFD, the date string is
+        // followed by hours, minutes and seconds as rationals.
+        for (date, hms, iso, unix) in [
+            (
+                "2024:02:29",
+                [12, 30, 0],
+                "2024-02-29T12:30:00Z",
+                1_709_209_800,
+            ),
+            (
+                "2023:12:31",
+                [23, 59, 59],
+                "2023-12-31T23:59:59Z",
+                1_704_067_199,
+            ),
+            (
+                "2024:01:01",
+                [0, 0, 0],
+                "2024-01-01T00:00:00Z",
+                1_704_067_200,
+            ),
+            (
+                "2024:03:01",
+                [0, 0, 1],
+                "2024-03-01T00:00:01Z",
+                1_709_251_201,
+            ),
+        ]
+        .iter()
+        {
+            let mut bytes = format!("{}\0\0", date).into_bytes();
+            for v in hms.iter() {
+                bytes.extend_from_slice(&(*v as u32).to_le_bytes());
+                bytes.extend_from_slice(&1u32.to_le_bytes());
+            }
+            let mut buf = BufReader {
+                cursor_stack: Vec::new(),
+                cursor: 0,
+                buffer: bytes,
+                big_endian: false,
+                big_tiff: false,
+            };
+            let date_entry = IfdEntry {
+                tag: DATESTAMP,
+                typ_e: ASCII,
+                count: 12,
+                offset: 0,
+            };
+            let time_entry = IfdEntry {
+                tag: TIMESTAMP,
+                typ_e: RATIONAL,
+                count: 3,
+                offset: 12,
+            };
+            let mut w = GpsInfo::new();
+
+            let date = read_value(&mut buf, &date_entry)?;
+            w.process_datestamp(date.text().unwrap())?;
+            w.process_timestamp(read_value(&mut buf, &time_entry)?.rationals());
+            assert_eq!(format_time(w.time.unwrap()), *iso);
+            assert_eq!(w.time, Some(*unix));
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn test_calendar() -> Result<()> {
+        let day = |date: &str| -> Result<u64> {
+            let mut w = GpsInfo::new();
+            w.process_datestamp(date)?;
+            Ok(w.time.unwrap() / 86_400)
+        };
+
+        assert_eq!(day("1970:01:01")?, 0);
+        assert_eq!(day("2023:02:01")? - day("2023:01:31")?, 1);
+        assert_eq!(day("2023:03:01")? - day("2023:02:28")?, 1);
+        assert_eq!(day("2024:03:01")? - day("2024:02:28")?, 2);
+        assert_eq!(day("2000:03:01")? - day("2000:02:28")?, 2);
+        assert_eq!(day("2025:01:01")? - day("2024:01:01")?, 366);
+        for date in [
+            "2023:02:29",
+            "2100:02:29",
+            "2024:04:31",
+            "2024:13:01",
+            "1969:12:31",
+        ]
+        .iter()
+        {
+            assert!(day(date).is_err(), "{}", date);
+        }
+        Ok(())
+    }
+
+    // Building blocks of little endian GPS IFDs.
+    fn ifd_entry(tag: u16, typ_e: u16, count: u32, offset: u32) -> Vec<u8> {
+        [
+            &tag.to_le_bytes()[..],
+            &typ_e.to_le_bytes(),
+            &count.to_le_bytes(),
+            &offset.to_le_bytes(),
+        ]
+        .concat()
+    }
+
+    #[test]
+    fn test_buf_reader() -> Result<()> {
+        let mut buf = BufReader {
+            cursor_stack: Vec::new(),
+            cursor: 0,
+            buffer: vec![1, 2, 3, 4, 5, 6],
+            big_endian: false,
+            big_tiff: false,
+        };
+        let mut bytes = [0u8; 4];
+
+        buf.read_exact(&mut bytes)?;
+        assert_eq!(bytes, [1, 2, 3, 4]);
+        // Across the end of the buffer: nothing is read, the cursor stays.
+        let e = buf.read_exact(&mut bytes).unwrap_err();
+        assert_eq!(e.kind(), ErrorKind::UnexpectedEof);
+        assert_eq!(buf.cursor, 4);
+        // While read() returns what is left, then nothing.
+        assert_eq!(buf.read(&mut bytes)?, 2);
+        assert_eq!(bytes[..2], [5, 6]);
+        assert_eq!(buf.read(&mut bytes)?, 0);
+        assert!(buf.read_exact(&mut bytes[..1]).is_err());
+        buf.read_exact(&mut [])?;
+
+        // Past the end of the buffer.
+        buf.cursor = 100;
+        assert_eq!(buf.read(&mut bytes)?, 0);
+        assert!(buf.read_exact(&mut bytes).is_err());
+        assert_eq!(buf.cursor, 100);
+        Ok(())
+    }
+
+    #[test]
+    fn test_read_struct() -> Result<()> {
+        let bytes = ifd_entry(LAT_V, RATIONAL, 3, 0x0102_0304);
+        let entry = read_struct::<IfdEntry, _>(&mut Cursor::new(&bytes))?;
+
+        assert_eq!(
+            (entry.tag, entry.typ_e, entry.count, entry.offset),
+            (LAT_V, RATIONAL, 3, 0x0102_0304)
+        );
+        assert_eq!(
+            entry.to_string(),
+            "tag: 0002, type: 5, count 3, offset 16909060"
+        );
+
+        // Short of the last byte.
+        let short = read_struct::<IfdEntry, _>(&mut Cursor::new(&bytes[..11]));
+        assert!(short.is_err_and(|e| e.kind() == ErrorKind::UnexpectedEof));
+        Ok(())
+    }
+
+    fn rationals(v: [u32; 3]) -> Vec<u8> {
+        v.iter()
+            .flat_map(|n| [n.to_le_bytes(), 1u32.to_le_bytes()].concat())
+            .collect()
+    }
+
+    // Buffer with the IFD of the entries at its start, followed by the data.
+    fn gps_buffer(entries: &[&Vec<u8>], data: &[u8]) -> BufReader {
+        let mut bytes = (entries.len() as u16).to_le_bytes().to_vec();
+        for e in entries.iter() {
+            bytes.extend_from_slice(e);
+        }
+        bytes.extend_from_slice(&[0; 4]);
+        bytes.extend_from_slice(data);
+        BufReader {
+            cursor_stack: Vec::new(),
+            cursor: 0,
+            buffer: bytes,
+            big_endian: false,
+            big_tiff: false,
+        }
+    }
+
+    #[test]
+    fn test_hemisphere_order() -> Result<()> {
+        // GPS IFD of 33°30'S 70°15'W, the references preceding or following
+        // the values.
+        let lat_q = ifd_entry(LAT_Q, ASCII, 2, u32::from(b'S'));
+        let lat_v = ifd_entry(LAT_V, RATIONAL, 3, 54);
+        let long_q = ifd_entry(LONG_Q, ASCII, 2, u32::from(b'W'));
+        let long_v = ifd_entry(LONG_V, RATIONAL, 3, 78);
+
+        for order in [
+            [&lat_q, &lat_v, &long_q, &long_v],
+            [&lat_v, &lat_q, &long_v, &long_q],
+            [&long_v, &lat_v, &long_q, &lat_q],
+        ] {
+            let data = [rationals([33, 30, 0]), rationals([70, 15, 0])].concat();
+            let mut buf = gps_buffer(&order, &data);
+            let mut waypoints: Vec<GpsInfo> = Vec::new();
+
+            process_gps_section(&mut buf, "test", None, &mut waypoints)?;
+            assert_eq!(waypoints[0].lat, -33.5);
+            assert_eq!(waypoints[0].longt, -70.25);
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn test_exif_sub_ifd() -> Result<()> {
+        // TIFF with the GPS pointer in the Exif sub-IFD: IFD0 at 8, Exif IFD
+        // at 26, GPS IFD of 33°30'S 70°15'W at 44 and its data at 98.
+        let ifd = |entry: Vec<u8>| [&1u16.to_le_bytes()[..], &entry, &[0; 4]].concat();
+        let tiff = |tag: u16, offset: u32| {
+            [
+                b"II*\0".to_vec(),
+                8u32.to_le_bytes().to_vec(),
+                ifd(ifd_entry(EXIF_IFD, LONG, 1, 26)),
+                ifd(ifd_entry(tag, LONG, 1, offset)),
+                4u16.to_le_bytes().to_vec(),
+                ifd_entry(LAT_Q, ASCII, 2, u32::from(b'S')),
+                ifd_entry(LAT_V, RATIONAL, 3, 98),
+                ifd_entry(LONG_Q, ASCII, 2, u32::from(b'W')),
+                ifd_entry(LONG_V, RATIONAL, 3, 122),
+                vec![0; 4],
+                rationals([33, 30, 0]),
+                rationals([70, 15, 0]),
+            ]
+            .concat()
+        };
+
+        let waypoints = parse_bytes(&tiff(GPS, 44), "sub.tif", None)?;
+        assert_eq!((waypoints[0].lat, waypoints[0].longt), (-33.5, -70.25));
+
+        // The Exif IFD pointing at itself instead.
+        let bytes = tiff(EXIF_IFD, 26);
+        let mut waypoints: Vec<GpsInfo> = Vec::new();
+        let e =
+            parse_stream(&mut Cursor::new(bytes), "loop.tif", &mut waypoints, None).unwrap_err();
+        assert_eq!(e.to_string(), Skip::NoGps.to_string());
+        Ok(())
+    }
+
+    #[test]
+    fn test_bad_reference() -> Result<()> {
+        let lat_v = ifd_entry(LAT_V, RATIONAL, 3, 54);
+        let long_q = ifd_entry(LONG_Q, ASCII, 2, u32::from(b'W'));
+        let long_v = ifd_entry(LONG_V, RATIONAL, 3, 78);
+        let data = [rationals([33, 30, 0]), rationals([70, 15, 0])].concat();
+
+        for reference in [u32::from(b'X'), 0, u32::MAX, 0xd800] {
+            let lat_q = ifd_entry(LAT_Q, ASCII, 2, reference);
+            let mut buf = gps_buffer(&[&lat_q, &lat_v, &long_q, &long_v], &data);
+            let mut waypoints: Vec<GpsInfo> = Vec::new();
+
+            let e = process_gps_section(&mut buf, "test", None, &mut waypoints).unwrap_err();
+            assert_eq!(e.kind(), ErrorKind::Other);
+            assert_eq!(e.to_string(), Skip::BadRef(LAT_Q).to_string());
+            assert!(waypoints.is_empty());
+        }
+
+        // Garbage anywhere in the file is reported, never panics.
+        let photo = fs::read("src/test_data/test1.jpg")?;
+        let mut seed: u32 = 12345;
+        let mut random = || {
+            seed ^= seed << 13;
+            seed ^= seed >> 17;
+            seed ^= seed << 5;
+            seed as usize
+        };
+        for _ in 0..2000 {
+            let mut bytes = photo.clone();
+            for _ in 0..1 + random() % 4 {
+                let i = random() % bytes.len();
+                bytes[i] = random() as u8;
+            }
+            let _ = parse_bytes(&bytes, "fuzz.jpg", None);
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn test_damaged_input() -> Result<()> {
+        // A segment length below the size of the length field itself.
+        let e = parse_bytes(b"\xff\xd8\xff\xe1\x00\x01garbage", "short.jpg", None).err();
+        let e = e.map(|e| e.to_string());
+        assert_eq!(e.unwrap(), "damaged file: JPEG segment length below 2");
+
+        // The GPS IFD of test1.jpg pointing past the end of the Exif data.
+        let mut bytes = fs::read("src/test_data/test1.jpg")?;
+        let tiff = bytes.windows(6).position(|id| id == EXIF_ID).unwrap() + 6;
+        let gps = tiff + 8 + 2 + 12 * usize::from(bytes[tiff + 8]);
+        let entry = (tiff..gps)
+            .step_by(12)
+            .map(|e| e + 10)
+            .find(|&e| bytes[e..e + 2] == GPS.to_le_bytes())
+            .unwrap();
+        bytes[entry + 8..entry + 12].copy_from_slice(&0xfff0u32.to_le_bytes());
+        let e = parse_bytes(&bytes, "offset.jpg", None).err().unwrap();
+        assert_eq!(e.kind(), ErrorKind::Other);
+        assert!(e.to_string().starts_with("damaged file: "));
+
+        // Neither stops the rest of the files from being processed.
+        let path = env::temp_dir().join(format!("exifgeo-damaged-{}.jpg", std::process::id()));
+        fs::write(&path, &bytes)?;
+        let files = [
+            path.to_string_lossy().to_string(),
+            "src/test_data/test1.jpg".into(),
+        ];
+        let waypoints = parse_files(&files, false, None, None)?;
+        assert_eq!(waypoints.len(), 1);
+        assert!(parse_files(&files, true, None, None).is_err());
+        fs::remove_file(&path)
+    }
+
+    #[test]
+    fn test_bad_offset() -> Result<()> {
+        // The time stamp points outside the data, the rest of the entries are
+        // still used.
+        let data = [
+            rationals([33, 30, 0]),
+            rationals([70, 15, 0]),
+            b"2024:01:01\0\0".to_vec(),
+        ]
+        .concat();
+        let lat_q = ifd_entry(LAT_Q, ASCII, 2, u32::from(b'S'));
+        let lat_v = ifd_entry(LAT_V, RATIONAL, 3, 78);
+        let long_q = ifd_entry(LONG_Q, ASCII, 2, u32::from(b'W'));
+        let long_v = ifd_entry(LONG_V, RATIONAL, 3, 102);
+        let time = ifd_entry(TIMESTAMP, RATIONAL, 3, 5000);
+        let date = ifd_entry(DATESTAMP, ASCII, 11, 126);
+        let mut waypoints: Vec<GpsInfo> = Vec::new();
+
+        let mut buf = gps_buffer(&[&lat_q, &lat_v, &long_q, &long_v, &time, &date], &data);
+        process_gps_section(&mut buf, "test", None, &mut waypoints)?;
+        assert_eq!((waypoints[0].lat, waypoints[0].longt), (-33.5, -70.25));
+        assert_eq!(waypoints[0].time, None);
+
+        // Nor does one starting inside the data but ending past it.
+        let time = ifd_entry(TIMESTAMP, RATIONAL, 3, 126);
+        let mut buf = gps_buffer(&[&lat_q, &lat_v, &long_q, &time, &long_v, &date], &data);
+        process_gps_section(&mut buf, "test", None, &mut waypoints)?;
+        assert_eq!((waypoints[1].lat, waypoints[1].longt), (-33.5, -70.25));
+        assert!(buf.cursor_stack.is_empty());
+
+        // Without a coordinate there is no waypoint though.
+        let lat_v = ifd_entry(LAT_V, RATIONAL, 3, 5000);
+        let mut buf = gps_buffer(&[&lat_q, &lat_v, &long_q, &long_v, &time, &date], &data);
+        let e = process_gps_section(&mut buf, "test", None, &mut waypoints).unwrap_err();
+        assert_eq!(e.to_string(), Skip::Incomplete.to_string());
+        Ok(())
+    }
+
+    #[test]
+    fn test_inline_values() -> Result<()> {
+        // Values of up to four bytes are kept in the entries themselves, the
+        // time stamp is three RATIONALs though, never inline.
+        let data = [rationals([33, 30, 0]), rationals([70, 15, 0])].concat();
+        let data_at = 2 + 12 * 6 + 4;
+        let lat_q = ifd_entry(LAT_Q, ASCII, 2, u32::from(b'S'));
+        let lat_v = ifd_entry(LAT_V, RATIONAL, 3, data_at);
+        let long_q = ifd_entry(LONG_Q, ASCII, 2, u32::from(b'W'));
+        let long_v = ifd_entry(LONG_V, RATIONAL, 3, data_at + 24);
+        let version = ifd_entry(GPS_VERSION, BYTE, 4, u32::from_le_bytes([2, 3, 0, 0]));
+        let differential = ifd_entry(DIFFERENTIAL, SHORT, 1, 1);
+        let time = ifd_entry(TIMESTAMP, SHORT, 2, 0x000c_000a);
+        let mut waypoints: Vec<GpsInfo> = Vec::new();
+
+        let entries = [&lat_q, &lat_v, &long_q, &long_v, &version, &differential];
+        process_gps_section(
+            &mut gps_buffer(&entries, &data),
+            "test",
+            None,
+            &mut waypoints,
+        )?;
+        assert_eq!(waypoints[0].gps_version, Some([2, 3, 0, 0]));
+        assert_eq!(waypoints[0].differential, Some(true));
+
+        let entries = [&lat_q, &lat_v, &long_q, &long_v, &version, &time];
+        let mut buf = gps_buffer(&entries, &data);
+        let e = process_gps_section(&mut buf, "test", None, &mut waypoints).unwrap_err();
+        assert_eq!(e.to_string(), Skip::BadType(TIMESTAMP).to_string());
+        Ok(())
+    }
+
+    #[test]
+    fn test_entry_types() -> Result<()> {
+        // Entries of unexpected types skip the photo rather than being read
+        // as garbage: a SHORT latitude and a datestamp of BYTEs.
+        let data = [
+            rationals([33, 30, 0]),
+            rationals([70, 15, 0]),
+            rationals([10, 0, 0]),
+        ]
+        .concat();
+        let data_at = 2 + 12 * 6 + 4;
+        let lat_q = ifd_entry(LAT_Q, ASCII, 2, u32::from(b'S'));
+        let lat_v = ifd_entry(LAT_V, RATIONAL, 3, data_at);
+        let long_q = ifd_entry(LONG_Q, ASCII, 2, u32::from(b'W'));
+        let long_v = ifd_entry(LONG_V, RATIONAL, 3, data_at + 24);
+        let time = ifd_entry(TIMESTAMP, RATIONAL, 3, data_at + 48);
+        let date = ifd_entry(DATESTAMP, BYTE, 4, u32::from_le_bytes(*b"2021"));
+        let mut waypoints: Vec<GpsInfo> = Vec::new();
+
+        let short_lat = ifd_entry(LAT_V, SHORT, 2, 0x001e_0021);
+        let entries = [&lat_q, &short_lat, &long_q, &long_v, &time, &date];
+        let mut buf = gps_buffer(&entries, &data);
+        let e = process_gps_section(&mut buf, "test", None, &mut waypoints).unwrap_err();
+        assert_eq!(e.kind(), ErrorKind::Other);
+        assert_eq!(e.to_string(), Skip::BadType(LAT_V).to_string());
+
+        let entries = [&lat_q, &lat_v, &long_q, &long_v, &time, &date];
+        let mut buf = gps_buffer(&entries, &data);
+        let e = process_gps_section(&mut buf, "test", None, &mut waypoints).unwrap_err();
+        assert_eq!(e.kind(), ErrorKind::Other);
+        assert_eq!(e.to_string(), Skip::BadType(DATESTAMP).to_string());
+
+        // So is a datestamp too short for "YYYY:MM:DD".
+        let date = ifd_entry(DATESTAMP, ASCII, 4, u32::from_le_bytes(*b"202\0"));
+        let entries = [&lat_q, &lat_v, &long_q, &long_v, &time, &date];
+        let mut buf = gps_buffer(&entries, &data);
+        let e = process_gps_section(&mut buf, "test", None, &mut waypoints).unwrap_err();
+        assert_eq!(e.to_string(), Skip::BadCount(DATESTAMP, 3).to_string());
+        assert!(waypoints.is_empty());
+        Ok(())
+    }
+
+    #[test]
+    fn test_speed() -> Result<()> {
+        // 10 knots, the unit can follow the value.
+        let data = [
+            rationals([33, 30, 0]),
+            rationals([70, 15, 0]),
+            rationals([10, 0, 0]),
+        ]
+        .concat();
+        let data_at = 2 + 12 * 6 + 4;
+        let lat_q = ifd_entry(LAT_Q, ASCII, 2, u32::from(b'S'));
+        let lat_v = ifd_entry(LAT_V, RATIONAL, 3, data_at);
+        let long_q = ifd_entry(LONG_Q, ASCII, 2, u32::from(b'W'));
+        let long_v = ifd_entry(LONG_V, RATIONAL, 3, data_at + 24);
+        let speed = ifd_entry(SPEED, RATIONAL, 1, data_at + 48);
+        let knots = ifd_entry(SPEED_REF, ASCII, 2, u32::from(b'N'));
+        let mut waypoints: Vec<GpsInfo> = Vec::new();
+
+        let entries = [&lat_q, &lat_v, &long_q, &long_v, &speed, &knots];
+        process_gps_section(
+            &mut gps_buffer(&entries, &data),
+            "a.jpg",
+            None,
+            &mut waypoints,
+        )?;
+        assert_eq!(format!("{:.4}", waypoints[0].speed.unwrap()), "5.1444");
+
+        // km/h is assumed without the reference, an unknown one drops the
+        // speed but not the waypoint.
+        let unknown = ifd_entry(SPEED_REF, ASCII, 2, u32::from(b'X'));
+        let entries = [&lat_q, &lat_v, &long_q, &long_v, &speed, &unknown];
+        process_gps_section(
+            &mut gps_buffer(&entries, &data),
+            "b.jpg",
+            None,
+            &mut waypoints,
+        )?;
+        let alt_ref = ifd_entry(ALT_REF, BYTE, 1, 0);
+        let entries = [&lat_q, &lat_v, &long_q, &long_v, &speed, &alt_ref];
+        process_gps_section(
+            &mut gps_buffer(&entries, &data),
+            "c.jpg",
+            None,
+            &mut waypoints,
+        )?;
+        assert_eq!(waypoints[1].speed, None);
+        assert_eq!(format!("{:.4}", waypoints[2].speed.unwrap()), "2.7778");
+
+        let output = String::from_utf8(write_gpx(&waypoints, "test")?).unwrap();
+        assert!(output.contains(
+            "<gpxtpx:TrackPointExtension><gpxtpx:speed>5.14</gpxtpx:speed>\
+             </gpxtpx:TrackPointExtension>"
+        ));
+        let mut parsed: Vec<GpsInfo> = Vec::new();
+        parse_gpx(&output, "test.gpx", &mut parsed)?;
+        assert_eq!(parsed[0].speed, Some(5.14));
+        assert_eq!(parsed[1].speed, None);
+        Ok(())
+    }
+
+    #[test]
+    fn test_polygon() -> Result<()> {
+        // An L shape, the notch at its top right is outside.
+        let polygon = parse_polygon("0,0; 0,4; 2,4; 2,2; 4,2; 4,0")?;
+
+        assert!(in_polygon(1.0, 1.0, &polygon));
+        assert!(in_polygon(1.0, 3.0, &polygon));
+        assert!(in_polygon(3.0, 1.0, &polygon));
+        assert!(!in_polygon(3.0, 3.0, &polygon));
+        assert!(!in_polygon(-1.0, 1.0, &polygon));
+        assert!(!in_polygon(1.0, 5.0, &polygon));
+
+        assert!(parse_polygon("0,0;0,4").is_err());
+        assert!(parse_polygon("0,0;0,4;4").is_err());
+        assert!(parse_polygon("0,0;0,4;95,0").is_err());
+
+        // A box spanning the antimeridian near Fiji, from 179E to 179W.
+        let polygon = parse_polygon("-16,179; -16,-179; -18,-179; -18,179")?;
+
+        assert!(in_polygon(-17.0, 179.5, &polygon));
+        assert!(in_polygon(-17.0, -179.5, &polygon));
+        assert!(in_polygon(-17.0, 180.0, &polygon));
+        assert!(!in_polygon(-17.0, 178.5, &polygon));
+        assert!(!in_polygon(-17.0, -178.5, &polygon));
+        assert!(!in_polygon(-17.0, 0.0, &polygon));
+        Ok(())
+    }
+
+    #[test]
+    fn test_dedup_order() -> Result<()> {
+        // Of the nearby waypoints sharing the time the southernmost one is
+        // kept, whatever order they come in.
+        let point = |seq: usize, lat: f64, longt: f64| GpsInfo {
+            lat,
+            longt,
+            time: Some(1000),
+            seq,
+            ..GpsInfo::new()
+        };
+        let mut opts = OutputOpts::new("Test map");
+        opts.template = Some(parse_template("{lat} {lon}")?);
+        let points = [
+            (10.00002, 20.0),
+            (10.00003, 20.0),
+            (10.00001, 20.1),
+            (10.00001, 20.0),
+        ];
+        let orders = [[0, 1, 2, 3], [3, 2, 1, 0], [2, 0, 3, 1], [1, 3, 0, 2]];
+
+        for show_dropped in [false, true] {
+            opts.show_dropped = show_dropped;
+            let outputs: Vec<AV> = orders
+                .iter()
+                .map(|order| {
+                    let mut buf: AV = AV::new();
+                    let mut waypoints: Vec<GpsInfo> = order
+                        .iter()
+                        .enumerate()
+                        .map(|(seq, &i)| point(seq, points[i].0, points[i].1))
+                        .collect();
+                    print_xml(&mut waypoints, &mut buf, &opts).map(|_| buf)
+                })
+                .collect::<Result<_>>()?;
+            assert!(outputs.iter().all(|o| *o == outputs[0]));
+            let expected = if show_dropped {
+                "10.00001 20.10000\n10.00002 20.00000\n10.00003 20.00000\n"
+            } else {
+                "10.00001 20.00000\n"
+            };
+            assert_eq!(std::str::from_utf8(&outputs[0]).unwrap(), expected);
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn test_distance_from() {
+        // Distances are meters as f64: antipodal points are half the
+        // circumference apart and sub-meter steps are not lost.
+        let a = GpsInfo {
+            lat: 10.0,
+            longt: 20.0,
+            ..GpsInfo::new()
+        };
+        let b = GpsInfo {
+            lat: -10.0,
+            longt: -160.0,
+            ..GpsInfo::new()
+        };
+        let c = GpsInfo {
+            lat: 10.000_004,
+            longt: 20.0,
+            ..GpsInfo::new()
+        };
+
+        assert!((a.distance_from(&b) - std::f64::consts::PI * EARTH_RADIUS).abs() < 1.0);
+        assert!((a.distance_from(&c) - 0.444_8).abs() < 1e-3);
+    }
+
+    #[test]
+    fn test_distance_from_fast() {
+        for (lat, longt, dlat, dlong) in [
+            (0.0, 0.0, 0.001, 0.002),
+            (45.0, 7.0, -0.004, 0.003),
+            (66.04576, -17.34345, 0.00003, -0.00006),
+            (-54.8, 179.999, 0.0005, 0.002),
+            (89.99, 10.0, 0.0, 0.01),
+        ]
+        .iter()
+        {
+            let a = GpsInfo {
+                lat: *lat,
+                longt: *longt,
+                ..GpsInfo::new()
+            };
+            let b = GpsInfo {
+                lat: lat + dlat,
+                longt: wrap_longitude(longt + dlong),
+                ..GpsInfo::new()
+            };
+            let exact = a.distance_from(&b);
+
+            assert!(exact < 1000.0);
+            assert!((a.distance_from_fast(&b) - exact).abs() <= exact * 0.01);
+        }
+    }
+
+    #[test]
+    fn test_local_time() -> Result<()> {
+        let mut waypoints: Vec<GpsInfo> = Vec::new();
+        let mut opts = OutputOpts::new("Test map");
+
+        // test9.jpg is Tokyo, UTC+9, test0.jpg is Sydney, UTC+10.
+        read_geotags("src/test_data/test9.jpg", &mut waypoints, None)?;
+        read_geotags("src/test_data/test0.jpg", &mut waypoints, None)?;
+        opts.offset_for = Some(nautical_offset);
+
+        let mut buf: AV = AV::new();
+        print_xml(&mut waypoints, &mut buf, &opts)?;
+        let output = std::str::from_utf8(&buf).unwrap();
+        assert!(output.contains("<time>2020-03-21T12:15:42+09:00</time>"));
+        assert!(output.contains("<time>2020-01-21T16:10:56+10:00</time>"));
+
+        // Local times read back are the same moments in UTC.
+        let mut parsed: Vec<GpsInfo> = Vec::new();
+        parse_gpx(output, "test.gpx", &mut parsed)?;
+        assert_eq!(parsed[0].time, waypoints[0].time);
+        assert_eq!(parsed[1].time, waypoints[1].time);
+        assert_eq!(
+            format_local_time(parsed[0].time.unwrap(), -16 * 3600),
+            "2020-01-20T14:10:56-16:00"
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn test_quality() {
+        let mut w = GpsInfo::new();
+
+        assert_eq!(w.quality(), None);
+        w.dop = Some(2.0);
+        assert_eq!(w.quality(), Some(2.0 * UERE));
+        w.h_error = Some(3.5);
+        assert_eq!(w.quality(), Some(3.5));
+    }
+
+    #[test]
+    fn test_split_exif() -> Result<()> {
+        // The Exif data of test14.jpg is split into two APP1 segments, the
+        // GPS IFD is in the second one.
+        let mut waypoints: Vec<GpsInfo> = Vec::new();
+
+        read_geotags("src/test_data/test14.jpg", &mut waypoints, None)?;
+        assert_eq!(waypoints.len(), 1);
+        assert!((waypoints[0].lat - 37.7749).abs() < 1e-6);
+        Ok(())
+    }
+
+    #[test]
+    fn test_truncated() -> Result<()> {
+        // test8.jpg cut inside the DRI segment length and right after it.
+        let bytes = fs::read("src/test_data/test8.jpg")?;
+        let mut waypoints: Vec<GpsInfo> = Vec::new();
+
+        for len in [9, 13].iter() {
+            let mut f = Cursor::new(&bytes[..*len]);
+            let e = parse_stream(&mut f, "test8.jpg", &mut waypoints, None).unwrap_err();
+            assert_eq!(e.kind(), ErrorKind::Other);
+            assert_eq!(e.to_string(), Skip::Truncated.to_string());
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn test_skip_non_image() {
+        let mut waypoints: Vec<GpsInfo> = Vec::new();
+
+        let e = read_geotags("src/test_data/result.txt", &mut waypoints, None).unwrap_err();
+        assert_eq!(e.kind(), ErrorKind::Other);
+        assert!(waypoints.is_empty());
+    }
+
+    #[test]
+    fn test_jfif_before_exif() -> Result<()> {
+        // test4.jpg carries the same Exif segment as test1.jpg, preceded by a
+        // JFIF APP0 segment.
+        let mut waypoints: Vec<GpsInfo> = Vec::new();
+
+        read_geotags("src/test_data/test1.jpg", &mut waypoints, None)?;
+        read_geotags("src/test_data/test4.jpg", &mut waypoints, None)?;
+        assert_eq!(waypoints.len(), 2);
+        assert_eq!(waypoints[0].lat, waypoints[1].lat);
+        assert_eq!(waypoints[0].longt, waypoints[1].longt);
+        assert_eq!(waypoints[0].time, waypoints[1].time);
+        Ok(())
+    }
+
+    // Timing baselines rather than tests, `#[bench]` needs a nightly
+    // compiler. Run them with `cargo test --release -- --ignored --nocapture`.
+    fn bench<F: FnMut()>(name: &str, iterations: u32, mut f: F) {
+        let start = Instant::now();
+
+        for _ in 0..iterations {
+            f();
+        }
+        println!(
+            "{}: {:.1} ns/iter",
+            name,
+            start.elapsed().as_nanos() as f64 / f64::from(iterations)
+        );
+    }
+
+    #[test]
+    #[ignore]
+    fn bench_distance_from() {
+        let a = GpsInfo {
+            lat: 66.04576,
+            longt: -17.34345,
+            ..GpsInfo::new()
+        };
+        let b = GpsInfo {
+            lat: 66.04579,
+            longt: -17.34351,
+            ..GpsInfo::new()
+        };
+
+        bench("distance_from", 1_000_000, || {
+            std::hint::black_box(std::hint::black_box(&a).distance_from(&b));
+        });
+        bench("distance_from_fast", 1_000_000, || {
+            std::hint::black_box(std::hint::black_box(&a).distance_from_fast(&b));
+        });
+    }
+
+    #[test]
+    #[ignore]
+    fn bench_parse_bytes() {
+        let bytes = fs::read("src/test_data/test1.jpg").unwrap();
+
+        bench("parse_bytes", 100_000, || {
+            std::hint::black_box(
+                parse_bytes(std::hint::black_box(&bytes), "test1.jpg", None).unwrap(),
+            );
+        });
+    }
+}
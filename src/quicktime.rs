@@ -0,0 +1,178 @@
+// Minimal QuickTime/MP4 reader: walks the box (atom) hierarchy looking for
+// the location and the creation time phones record in their videos. Only the
+// moov/udta/©xyz location is supported, not the one in the moov/meta keys.
+use std::io::{Error, ErrorKind, Read, Result, Seek, SeekFrom};
+
+const MOOV: [u8; 4] = *b"moov"; // Movie metadata.
+const MVHD: [u8; 4] = *b"mvhd"; // Movie header.
+const UDTA: [u8; 4] = *b"udta"; // User data.
+const XYZ: [u8; 4] = *b"\xa9xyz"; // ISO 6709 location.
+
+// The location is two levels down, anything nested much deeper is not a
+// movie this reader knows, and could exhaust the stack.
+const MAX_DEPTH: usize = 8;
+
+// Seconds from Jan 1 1904, the QuickTime epoch, to Jan 1 1970.
+const EPOCH_OFFSET: u64 = 2_082_844_800;
+
+#[derive(Default)]
+pub struct Movie {
+    pub location: Option<String>, // ISO 6709 string, see parse_iso6709().
+    pub created: Option<u64>,     // Unix time, UTC.
+}
+
+fn bad_movie(why: &str) -> Error {
+    Error::new(
+        ErrorKind::InvalidData,
+        format!("bad QuickTime file: {}", why),
+    )
+}
+
+pub fn read_movie<R: Read + Seek>(f: &mut R) -> Result<Movie> {
+    let mut movie = Movie::default();
+    let end = f.seek(SeekFrom::End(0))?;
+
+    walk(f, 0, end, 0, &mut movie)?;
+    Ok(movie)
+}
+
+// Go over the boxes between `start` and `end`, descending into the ones
+// which can hold the location.
+fn walk<R: Read + Seek>(
+    f: &mut R,
+    start: u64,
+    end: u64,
+    depth: usize,
+    movie: &mut Movie,
+) -> Result<()> {
+    let mut pos = start;
+
+    if depth > MAX_DEPTH {
+        return Err(bad_movie("boxes nested too deep"));
+    }
+    while pos + 8 <= end {
+        let mut header = [0u8; 8];
+
+        f.seek(SeekFrom::Start(pos))?;
+        f.read_exact(&mut header)?;
+        let size = u32::from_be_bytes([header[0], header[1], header[2], header[3]]);
+        let kind = [header[4], header[5], header[6], header[7]];
+        let mut payload = pos + 8;
+        let box_end = match size {
+            0 => end, // The box extends to the end of the file.
+            1 => {
+                let mut large = [0u8; 8];
+
+                f.read_exact(&mut large)?;
+                payload += 8;
+                pos.checked_add(u64::from_be_bytes(large))
+                    .ok_or_else(|| bad_movie("bad box size"))?
+            }
+            _ => pos + u64::from(size),
+        };
+        if box_end < payload || box_end > end {
+            return Err(bad_movie("bad box size"));
+        }
+
+        match kind {
+            MOOV | UDTA => walk(f, payload, box_end, depth + 1, movie)?,
+            MVHD => movie.created = read_creation_time(f, box_end - payload)?,
+            XYZ => movie.location = Some(read_location(f, box_end - payload)?),
+            _ => {}
+        }
+        pos = box_end;
+    }
+    Ok(())
+}
+
+// Creation time in the movie header, zero stands for unknown.
+fn read_creation_time<R: Read>(f: &mut R, size: u64) -> Result<Option<u64>> {
+    let mut header = [0u8; 12];
+    if size < header.len() as u64 {
+        return Err(bad_movie("short movie header"));
+    }
+
+    f.read_exact(&mut header)?;
+    let created = if header[0] == 1 {
+        u64::from_be_bytes([
+            header[4], header[5], header[6], header[7], header[8], header[9], header[10],
+            header[11],
+        ])
+    } else {
+        u64::from(u32::from_be_bytes([
+            header[4], header[5], header[6], header[7],
+        ]))
+    };
+    Ok(created.checked_sub(EPOCH_OFFSET).filter(|_| created != 0))
+}
+
+// The location is a string preceded by its length and language code.
+fn read_location<R: Read>(f: &mut R, size: u64) -> Result<String> {
+    let mut header = [0u8; 4];
+    if size < header.len() as u64 {
+        return Err(bad_movie("short location"));
+    }
+
+    f.read_exact(&mut header)?;
+    let len = u16::from_be_bytes([header[0], header[1]]) as u64;
+    if len > size - header.len() as u64 {
+        return Err(bad_movie("short location"));
+    }
+    let mut text = vec![0u8; len as usize];
+    f.read_exact(&mut text)?;
+    Ok(String::from_utf8_lossy(&text).to_string())
+}
+
+// A signed ISO 6709 angle, the integer part having `digits` digits of
+// degrees, optionally followed by two digits of minutes and two digits of
+// seconds, the last of them can have a fraction.
+fn iso6709_angle(text: &str, digits: usize, limit: f64) -> Option<f64> {
+    let sign = if text.starts_with('-') { -1.0 } else { 1.0 };
+    let body = &text[1..];
+    let int_len = body.find('.').unwrap_or(body.len());
+    if !body[..int_len].bytes().all(|b| b.is_ascii_digit()) || int_len < digits {
+        return None;
+    }
+
+    let (degrees, minutes, seconds) = match int_len - digits {
+        0 => (body.parse::<f64>().ok()?, 0.0, 0.0),
+        2 => (
+            body[..digits].parse::<f64>().ok()?,
+            body[digits..].parse::<f64>().ok()?,
+            0.0,
+        ),
+        4 => (
+            body[..digits].parse::<f64>().ok()?,
+            body[digits..digits + 2].parse::<f64>().ok()?,
+            body[digits + 2..].parse::<f64>().ok()?,
+        ),
+        _ => return None,
+    };
+    let angle = degrees + minutes / 60.0 + seconds / 3600.0;
+    if minutes >= 60.0 || seconds >= 60.0 || angle > limit {
+        return None;
+    }
+    Some(sign * angle)
+}
+
+// Parse an ISO 6709 location like "+37.7749-122.4194+010.000/" into
+// latitude, longitude and the optional altitude in meters. Coordinates can
+// be given as degrees, degrees and minutes or degrees, minutes and seconds.
+pub fn parse_iso6709(text: &str) -> Option<(f64, f64, Option<f64>)> {
+    let end = text.find(['/', 'C']).unwrap_or(text.len());
+    let text = &text[..end];
+    let mut starts: Vec<usize> = text.match_indices(['+', '-']).map(|(i, _)| i).collect();
+    if starts.first() != Some(&0) || starts.len() < 2 || starts.len() > 3 {
+        return None;
+    }
+
+    starts.push(text.len());
+    let parts: Vec<&str> = starts.windows(2).map(|w| &text[w[0]..w[1]]).collect();
+    let lat = iso6709_angle(parts[0], 2, 90.0)?;
+    let longt = iso6709_angle(parts[1], 3, 180.0)?;
+    let alt = match parts.get(2) {
+        Some(alt) => Some(alt.parse::<f64>().ok()?),
+        None => None,
+    };
+    Some((lat, longt, alt))
+}
@@ -0,0 +1,117 @@
+// Minimal ZIP archive reader: only what is needed to get photos out of an
+// archive in memory. Entries can be stored or deflated, encryption, ZIP64 and
+// multi-disk archives are not supported.
+use crate::inflate::inflate;
+use std::io::{Error, ErrorKind, Result};
+
+const EOCD_SIGNATURE: u32 = 0x0605_4b50; // End of central directory record.
+const CENTRAL_SIGNATURE: u32 = 0x0201_4b50; // Central directory file header.
+const LOCAL_SIGNATURE: u32 = 0x0403_4b50; // Local file header.
+const EOCD_SIZE: usize = 22;
+const CENTRAL_SIZE: usize = 46;
+const LOCAL_SIZE: usize = 30;
+const MAX_COMMENT: usize = 0xffff;
+
+const STORED: u16 = 0;
+const DEFLATED: u16 = 8;
+
+// An archive entry as described in the central directory.
+pub struct Entry {
+    pub name: String,
+    method: u16,
+    compressed_size: usize,
+    size: usize,
+    offset: usize, // Of the local file header.
+}
+
+fn bad_archive(why: &str) -> Error {
+    Error::new(ErrorKind::InvalidData, format!("bad ZIP archive: {}", why))
+}
+
+fn u16_at(data: &[u8], pos: usize) -> Result<u16> {
+    match data.get(pos..pos + 2) {
+        Some(b) => Ok(u16::from_le_bytes([b[0], b[1]])),
+        None => Err(bad_archive("truncated")),
+    }
+}
+
+fn u32_at(data: &[u8], pos: usize) -> Result<u32> {
+    match data.get(pos..pos + 4) {
+        Some(b) => Ok(u32::from_le_bytes([b[0], b[1], b[2], b[3]])),
+        None => Err(bad_archive("truncated")),
+    }
+}
+
+// List the entries of the archive, directories are omitted.
+pub fn entries(archive: &[u8]) -> Result<Vec<Entry>> {
+    // The end of central directory record is followed by a comment of up to
+    // 64K, look for it starting at the end.
+    if archive.len() < EOCD_SIZE {
+        return Err(bad_archive("too short"));
+    }
+    let last = archive.len() - EOCD_SIZE;
+    let eocd = (last.saturating_sub(MAX_COMMENT)..=last)
+        .rev()
+        .find(|&pos| u32_at(archive, pos).ok() == Some(EOCD_SIGNATURE))
+        .ok_or_else(|| bad_archive("no central directory"))?;
+
+    let count = u16_at(archive, eocd + 10)? as usize;
+    let mut pos = u32_at(archive, eocd + 16)? as usize;
+    let mut entries: Vec<Entry> = Vec::with_capacity(count);
+
+    for _ in 0..count {
+        if u32_at(archive, pos)? != CENTRAL_SIGNATURE {
+            return Err(bad_archive("bad central directory"));
+        }
+        let name_len = u16_at(archive, pos + 28)? as usize;
+        let extra_len = u16_at(archive, pos + 30)? as usize;
+        let comment_len = u16_at(archive, pos + 32)? as usize;
+        let name = archive
+            .get(pos + CENTRAL_SIZE..pos + CENTRAL_SIZE + name_len)
+            .ok_or_else(|| bad_archive("truncated"))?;
+        let entry = Entry {
+            name: String::from_utf8_lossy(name).to_string(),
+            method: u16_at(archive, pos + 10)?,
+            compressed_size: u32_at(archive, pos + 20)? as usize,
+            size: u32_at(archive, pos + 24)? as usize,
+            offset: u32_at(archive, pos + 42)? as usize,
+        };
+
+        if !entry.name.ends_with('/') {
+            entries.push(entry);
+        }
+        pos += CENTRAL_SIZE + name_len + extra_len + comment_len;
+    }
+    Ok(entries)
+}
+
+// Retrieve the uncompressed contents of the entry.
+pub fn read(archive: &[u8], entry: &Entry) -> Result<Vec<u8>> {
+    let pos = entry.offset;
+
+    if u32_at(archive, pos)? != LOCAL_SIGNATURE {
+        return Err(bad_archive("bad local header"));
+    }
+    let start = pos
+        + LOCAL_SIZE
+        + u16_at(archive, pos + 26)? as usize
+        + u16_at(archive, pos + 28)? as usize;
+    let data = archive
+        .get(start..start + entry.compressed_size)
+        .ok_or_else(|| bad_archive("truncated"))?;
+
+    let contents = match entry.method {
+        STORED => data.to_vec(),
+        DEFLATED => inflate(data, entry.size)?,
+        method => {
+            return Err(Error::new(
+                ErrorKind::InvalidData,
+                format!("unsupported ZIP compression method {}", method),
+            ))
+        }
+    };
+    if contents.len() != entry.size {
+        return Err(bad_archive("entry size mismatch"));
+    }
+    Ok(contents)
+}